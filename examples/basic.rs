@@ -1,13 +1,12 @@
 extern crate cree;
 use cree::api::{CreeOptions, CreeServer};
-use tokio;
 
 #[tokio::main]
 async fn main() {
-    let mut server = CreeServer::init(CreeOptions::HttpServer);
-    server.listen(81);
+    let mut server = CreeServer::init(CreeOptions::http());
+    server.listen(81).unwrap();
 
-    while let Ok((req, mut res)) = server.accept().await {
+    while let Ok((_req, mut res)) = server.accept().await {
         res.send(b"Hello from cree server!").await.unwrap();
     }
 }
@@ -0,0 +1,63 @@
+extern crate cree;
+use chrono::Duration;
+use cree::api::{Cookie, CookieKey, CreeOptions, CreeServer, SameSite};
+use std::sync::Arc;
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+    // use a long random secret which stays the same between restarts
+    let key = Arc::new(CookieKey::new(b"change this to a long random secret"));
+
+    let login_key = key.clone();
+    server
+        .get("/login", move |_req, mut res| {
+            let key = login_key.clone();
+            async move {
+                res.set_cookie(
+                    &Cookie::new("theme", "dark")
+                        .unwrap()
+                        .path("/")
+                        .max_age(Duration::days(30)),
+                )
+                .unwrap();
+                res.set_cookie(
+                    &Cookie::signed("user", "john", &key)
+                        .unwrap()
+                        .path("/")
+                        .http_only(true)
+                        .same_site(SameSite::Lax),
+                )
+                .unwrap();
+                res.set_cookie(
+                    &Cookie::encrypted("secret", "42", &key)
+                        .unwrap()
+                        .path("/")
+                        .http_only(true),
+                )
+                .unwrap();
+                res.send(b"Logged in.").await.unwrap();
+            }
+        })
+        .get("/profile", move |req, mut res| {
+            let key = key.clone();
+            async move {
+                let cookies = req.cookies();
+                let body = format!(
+                    "theme: {:?}, user: {:?}, secret: {:?}",
+                    cookies.get("theme"),
+                    cookies.get_signed("user", &key),
+                    cookies.get_encrypted("secret", &key)
+                );
+                res.send(body.as_bytes()).await.unwrap();
+            }
+        })
+        .get("/logout", |_req, mut res| async move {
+            res.set_cookie(&Cookie::new("user", "").unwrap().path("/").expire())
+                .unwrap();
+            res.send(b"Logged out.").await.unwrap();
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
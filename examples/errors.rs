@@ -0,0 +1,37 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer, HTTPStatus};
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    server
+        .error_page(
+            HTTPStatus::NotFound,
+            "text/html",
+            b"<h1>404</h1><p>This page doesn't exist.</p>",
+        )
+        .error_page(
+            HTTPStatus::ServerError,
+            "text/html",
+            b"<h1>500</h1><p>Something went wrong.</p>",
+        )
+        // answered with the 500 page, the connection stays open
+        .get("/panic", |_req, _res| async move {
+            panic!("Something went wrong.");
+        })
+        // a handler which forgets to respond is answered with the 500 page as well
+        .get("/forgot", |_req, _res| async move {})
+        .get("/users/:id", |req, mut res| async move {
+            let result = match req.param("id").and_then(|id| id.parse::<u32>().ok()) {
+                Some(id) => res.send(format!("User {}", id).as_bytes()).await,
+                None => res.send_error(HTTPStatus::NotFound).await,
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to send the response: {}", e);
+            }
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
@@ -0,0 +1,64 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer, HTTPStatus};
+use serde_derive::Deserialize;
+
+#[derive(Deserialize)]
+struct User {
+    name: String,
+    age: u8,
+}
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    server
+        .get("/search", |req, mut res| async move {
+            let query = req.query_params();
+            let tags = query.get_all("tag").join(", ");
+            res.send(format!("Tags: {}", tags).as_bytes())
+                .await
+                .unwrap();
+        })
+        .post("/login", |mut req, mut res| async move {
+            let form = req.form().await.unwrap();
+            let username = form.get("username").unwrap_or("anonymous");
+            res.send(format!("Hello {}", username).as_bytes())
+                .await
+                .unwrap();
+        })
+        .post("/users", |mut req, mut res| async move {
+            match req.json::<User>().await {
+                Ok(user) => {
+                    let body = format!("{} is {} years old", user.name, user.age);
+                    res.send(body.as_bytes()).await.unwrap();
+                }
+                Err(e) => {
+                    res.set_status(HTTPStatus::BadRequest);
+                    res.send(e.to_string().as_bytes()).await.unwrap();
+                }
+            }
+        })
+        .post("/upload", |req, mut res| async move {
+            let mut multipart = req.multipart().unwrap();
+            let mut summary = String::new();
+            while let Some(mut part) = multipart.next_part().await.unwrap() {
+                if part.is_file() {
+                    // files are read in chunks instead of being buffered
+                    let mut size = 0;
+                    while let Some(chunk) = part.chunk().await.unwrap() {
+                        size += chunk.len();
+                    }
+                    let filename = part.filename.clone().unwrap_or_default();
+                    summary.push_str(&format!("{}: {} ({} bytes)\n", part.name, filename, size));
+                } else {
+                    let value = part.text().await.unwrap();
+                    summary.push_str(&format!("{} = {}\n", part.name, value));
+                }
+            }
+            res.send(summary.as_bytes()).await.unwrap();
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
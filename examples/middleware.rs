@@ -0,0 +1,30 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer, HTTPStatus};
+use cree::middleware::{compression, logger};
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    server
+        .use_middleware(logger)
+        .use_middleware(compression)
+        .use_middleware(|req, mut res, next| async move {
+            if req.path.starts_with("/admin") && req.headers.get("authorization").is_none() {
+                res.set_status(HTTPStatus::Unauthorized);
+                res.send(b"Unauthorized").await.unwrap();
+                return;
+            }
+            res.set_header("X-Powered-By", "cree").unwrap();
+            next.run(req, res).await
+        })
+        .get("/", |_req, mut res| async move {
+            res.send(b"Hello from cree server!").await.unwrap();
+        })
+        .get("/admin", |_req, mut res| async move {
+            res.send(b"Welcome back.").await.unwrap();
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
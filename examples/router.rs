@@ -0,0 +1,48 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer, HTTPStatus};
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    server
+        .get("/", |_req, mut res| async move {
+            res.send(b"Hello from cree server!").await.unwrap();
+        })
+        .get("/users/:id", |req, mut res| async move {
+            let id = req.param("id").unwrap();
+            res.send(format!("User {}", id).as_bytes()).await.unwrap();
+        })
+        .put("/users/:id", |req, mut res| async move {
+            let id = req.param("id").unwrap();
+            res.send(format!("Updated user {}", id).as_bytes())
+                .await
+                .unwrap();
+        })
+        .delete("/users/:id", |req, mut res| async move {
+            let id = req.param("id").unwrap();
+            res.send(format!("Deleted user {}", id).as_bytes())
+                .await
+                .unwrap();
+        })
+        .get("/old", |_req, mut res| async move {
+            res.set_status(HTTPStatus::MovedPermanently);
+            res.set_header("Location", "/").unwrap();
+            res.send(&[]).await.unwrap();
+        })
+        .get("/teapot", |_req, mut res| async move {
+            res.set_status(HTTPStatus::Custom(418, String::from("I'm a teapot")));
+            res.send(b"Short and stout.").await.unwrap();
+        })
+        .get("/files/*", |req, mut res| async move {
+            let file = req.param("*").unwrap();
+            res.send(format!("File {}", file).as_bytes()).await.unwrap();
+        })
+        .fallback(|_req, mut res| async move {
+            res.set_status(HTTPStatus::NotFound);
+            res.send(b"Nothing here.").await.unwrap();
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
@@ -0,0 +1,33 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer};
+use cree::session::{MemoryStore, Sessions};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    // FileStore::new("./sessions") keeps the sessions between restarts
+    server.use_middleware(
+        Sessions::new(MemoryStore::new())
+            .ttl(Duration::from_secs(30 * 60))
+            .middleware(),
+    );
+
+    server
+        .get("/", |req, mut res| async move {
+            let session = req.session().unwrap();
+            let visits = session.get::<u32>("visits").unwrap_or(0) + 1;
+            session.insert("visits", &visits).unwrap();
+            res.send(format!("Visits: {}", visits).as_bytes())
+                .await
+                .unwrap();
+        })
+        .get("/logout", |req, mut res| async move {
+            req.session().unwrap().destroy();
+            res.send(b"Logged out.").await.unwrap();
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
@@ -0,0 +1,33 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    // Ctrl+C or SIGTERM stops the server, slow requests get 10 seconds to finish
+    server
+        .shutdown_on_signal(Duration::from_secs(10))
+        .get("/", |_req, mut res| async move {
+            res.send(b"Hello from cree server!").await.unwrap();
+        })
+        .get("/slow", |_req, mut res| async move {
+            tokio::time::sleep(Duration::from_secs(3)).await;
+            res.send(b"Finished.").await.unwrap();
+        });
+
+    // shuts down from a route instead of a signal
+    let handle = server.shutdown_handle();
+    server.post("/shutdown", move |_req, mut res| {
+        let handle = handle.clone();
+        async move {
+            res.send(b"Shutting down.").await.unwrap();
+            tokio::spawn(async move { handle.shutdown().await });
+        }
+    });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+    println!("Server stopped.");
+}
@@ -0,0 +1,43 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer, Event};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    server
+        // counts up every second, a reconnecting client continues where it left off
+        .get("/counter", |_req, res| async move {
+            let mut stream = match res.event_stream().await {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let mut count = stream
+                .last_event_id()
+                .and_then(|id| id.parse::<u64>().ok())
+                .map_or(0, |id| id + 1);
+            loop {
+                let event = Event::new(&count.to_string())
+                    .event("count")
+                    .id(&count.to_string())
+                    .retry(Duration::from_secs(3));
+                if stream.send(&event).await.is_err() {
+                    break;
+                }
+                count += 1;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+        // sends a few lines and ends the stream
+        .get("/once", |_req, res| async move {
+            if let Ok(mut stream) = res.event_stream().await {
+                let _ = stream.comment("hello").await;
+                let _ = stream.send_data("first line\nsecond line").await;
+                let _ = stream.close().await;
+            }
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
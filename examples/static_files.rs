@@ -0,0 +1,13 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer};
+use cree::static_files::StaticFiles;
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    server.use_middleware(StaticFiles::new("./public").middleware());
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
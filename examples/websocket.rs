@@ -0,0 +1,50 @@
+extern crate cree;
+use cree::api::{CreeOptions, CreeServer};
+use cree::websocket::{Message, WebSocket};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    let mut server = CreeServer::init(CreeOptions::http());
+
+    server
+        // echoes every message back
+        .get("/echo", |req, res| async move {
+            let mut socket = match WebSocket::upgrade(&req, res).await {
+                Ok(socket) => socket,
+                Err(_) => return,
+            };
+            while let Ok(Some(message)) = socket.receive().await {
+                let result = match message {
+                    Message::Text(text) => socket.send_text(&text).await,
+                    Message::Binary(data) => socket.send_binary(&data).await,
+                    _ => Ok(()),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+        })
+        // pushes a message every second until the client leaves
+        .get("/clock", |req, res| async move {
+            let socket = match WebSocket::upgrade(&req, res).await {
+                Ok(socket) => socket,
+                Err(_) => return,
+            };
+            let (mut receiver, sender) = socket.split();
+            let push = tokio::spawn(async move {
+                loop {
+                    let now = chrono::Utc::now().to_rfc3339();
+                    if sender.send_text(&now).await.is_err() {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            });
+            while let Ok(Some(_)) = receiver.receive().await {}
+            push.abort();
+        });
+
+    server.listen(81).unwrap();
+    server.serve().await;
+}
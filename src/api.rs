@@ -1,101 +1,554 @@
-use std::{collections::HashMap, io, net::SocketAddr, path::PathBuf, pin::Pin, sync::Arc};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::core::http::Encoding;
-use chrono::Utc;
 use futures::{future::join_all, Future};
 use tokio::{
-    io::{AsyncWriteExt, WriteHalf},
+    io::AsyncWriteExt,
     net::{TcpListener, TcpSocket, TcpStream},
     sync::mpsc,
-    sync::{mpsc::Receiver, Mutex},
+    sync::{mpsc::Receiver, Semaphore},
     task::JoinHandle,
+    time,
 };
 
-use crate::core::tcp::PersistentTcpConnection;
+use crate::core::http::error_pages::ErrorPages;
+use crate::core::shutdown::ShutdownSignal;
+use crate::core::tcp::{ConnectionLimits, PersistentTcpConnection};
+use crate::core::tls::{connection::TLSConnection, TLSConfig};
+use crate::error::{ErrorKind, HttpError, TcpError};
+use crate::middleware::Next;
+use crate::router::Router;
+use crate::Error;
 
-use crate::core::{
-    http::{
-        codes::{get_phrase_from_code, HTTPStatus},
-        protocol::{Method, Request, Response},
-    },
-    tcp::TCP_MAX_MESSAGES,
+use crate::core::tcp::TCP_MAX_MESSAGES;
+
+pub use crate::core::http::{
+    body::{FormData, Multipart, Part},
+    codes::HTTPStatus,
+    cookies::{Cookie, CookieJar, CookieKey, SameSite},
+    headers::HeaderMap,
+    protocol::{Method, Request, Response},
+    sse::{Event, EventStream},
 };
+pub use crate::core::shutdown::ShutdownHandle;
 
 #[derive(Clone)]
-pub enum CreeOptions {
-    HttpServer,
-    HttpsServer {
+enum Protocol {
+    Http,
+    Https {
         certificate: PathBuf,
         private_key: PathBuf,
     },
 }
+
+/// Configuration of a server, every limit has a default so only the ones which differ have to be set.
+///
+/// ```ignore
+/// let options = CreeOptions::http()
+///     .bind([127, 0, 0, 1])
+///     .bind(Ipv6Addr::LOCALHOST)
+///     .max_body_size(M_BYTE)
+///     .max_connections(512);
+/// ```
+#[derive(Clone)]
+pub struct CreeOptions {
+    protocol: Protocol,
+    addresses: Vec<IpAddr>,
+    limits: ConnectionLimits,
+    max_connections: Option<usize>,
+}
+
+impl CreeOptions {
+    pub fn http() -> CreeOptions {
+        CreeOptions::new(Protocol::Http)
+    }
+    /// Serves HTTPS with a PEM or DER certificate chain (leaf first) and a PKCS#1, PKCS#8 or SEC1 private key.
+    pub fn https(certificate: PathBuf, private_key: PathBuf) -> CreeOptions {
+        CreeOptions::new(Protocol::Https {
+            certificate,
+            private_key,
+        })
+    }
+    fn new(protocol: Protocol) -> CreeOptions {
+        CreeOptions {
+            protocol,
+            addresses: vec![],
+            limits: ConnectionLimits::default(),
+            max_connections: None,
+        }
+    }
+
+    /// Adds an address to listen on, can be called multiple times. Defaults to 0.0.0.0.
+    pub fn bind<A: Into<IpAddr>>(mut self, address: A) -> CreeOptions {
+        self.addresses.push(address.into());
+        self
+    }
+    /// Time an idle connection is kept open between requests.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> CreeOptions {
+        self.limits.keep_alive_timeout = timeout;
+        self
+    }
+    /// Number of requests answered on a connection before it is closed.
+    pub fn max_requests(mut self, max_requests: u32) -> CreeOptions {
+        self.limits.max_requests = max_requests.max(1);
+        self
+    }
+    /// Time a client has to send the head of a request, answered with `408` afterwards.
+    /// HTTPS clients have the same time to complete the TLS handshake.
+    pub fn header_timeout(mut self, timeout: Duration) -> CreeOptions {
+        self.limits.header_timeout = timeout;
+        self
+    }
+    /// Time a client may stay silent while sending a body, answered with `408` afterwards.
+    pub fn body_timeout(mut self, timeout: Duration) -> CreeOptions {
+        self.limits.body_timeout = timeout;
+        self
+    }
+    /// Size of the request line and headers in bytes, larger heads are answered with `431`.
+    pub fn max_header_size(mut self, size: usize) -> CreeOptions {
+        self.limits.max_header_size = size;
+        self
+    }
+    /// Size of a request body in bytes, larger bodies are answered with `413`.
+    pub fn max_body_size(mut self, size: usize) -> CreeOptions {
+        self.limits.max_body_size = Some(size);
+        self
+    }
+    /// Number of connections served at once, including TLS handshakes in progress. Further HTTP
+    /// connections are answered with `503`, further HTTPS connections are closed.
+    pub fn max_connections(mut self, max_connections: usize) -> CreeOptions {
+        self.max_connections = Some(max_connections);
+        self
+    }
+}
+
 pub struct CreeServer {
     options: CreeOptions,
-    address: SocketAddr,
     http_listener_thread: Option<JoinHandle<()>>,
     http_listener_receiver: Option<Receiver<(Request, Response)>>,
+    router: Router,
+    shutdown: ShutdownHandle,
+    error_pages: Arc<ErrorPages>,
+}
+
+// everything a connection needs from the server
+#[derive(Clone)]
+struct ConnectionContext {
+    tx: mpsc::Sender<(Request, Response)>,
+    shutdown: ShutdownSignal,
+    error_pages: Arc<ErrorPages>,
+    limits: ConnectionLimits,
+    tls_config: Option<Arc<TLSConfig>>,
+    connections: Option<Arc<Semaphore>>,
 }
 
 impl CreeServer {
     pub fn init(options: CreeOptions) -> CreeServer {
-        let port: u16 = match options {
-            CreeOptions::HttpServer => 80,
-            CreeOptions::HttpsServer { .. } => 443,
-        };
         CreeServer {
             options,
-            address: SocketAddr::from(([0, 0, 0, 0], port)),
             http_listener_thread: None,
             http_listener_receiver: None,
+            router: Router::new(),
+            shutdown: ShutdownHandle::new(),
+            error_pages: Arc::new(ErrorPages::new()),
         }
     }
-    pub fn listen(&mut self, port: u16) {
-        self.address.set_port(port);
+    /// Listens on the port of every bound address, addresses which can't be bound are skipped.
+    ///
+    /// Fails if the certificate or private key of an HTTPS server can't be loaded or if none of
+    /// the addresses could be bound.
+    pub fn listen(&mut self, port: u16) -> Result<(), Error> {
+        let mut addresses: Vec<SocketAddr> = self
+            .options
+            .addresses
+            .iter()
+            .map(|address| SocketAddr::new(*address, port))
+            .collect();
+        if addresses.is_empty() {
+            addresses.push(SocketAddr::from(([0, 0, 0, 0], port)));
+        }
+
+        let tls_config = match &self.options.protocol {
+            Protocol::Http => None,
+            Protocol::Https {
+                certificate,
+                private_key,
+            } => Some(Arc::new(TLSConfig::load(certificate, private_key)?)),
+        };
+
+        let mut listeners = vec![];
+        let mut bind_error = None;
+        for address in addresses {
+            match bind(address) {
+                Ok(listener) => {
+                    println!("Listening on {}", address);
+                    listeners.push(listener);
+                }
+                Err(e) => {
+                    eprintln!("Failed to listen on {}: {}", address, e);
+                    bind_error = Some(e);
+                }
+            }
+        }
+        if listeners.is_empty() {
+            let error = Error::new("None of the addresses could be bound.", TcpError::Bind);
+            return Err(match bind_error {
+                Some(e) => error.with_source(e),
+                None => error,
+            });
+        }
 
         let (tx, rx) = mpsc::channel(TCP_MAX_MESSAGES as usize);
-        let address = (self.address).clone();
         let options = self.options.clone();
+        let shutdown = self.shutdown.signal();
+        let error_pages = self.error_pages.clone();
         let listener_thread = tokio::spawn(async move {
-            match options {
-                CreeOptions::HttpServer => {
-                    let listener = TcpListener::bind(address).await.unwrap();
-                    println!("Listening on {}", address);
+            // the server was already shut down
+            let shutdown = match shutdown {
+                Some(shutdown) => shutdown,
+                None => return,
+            };
+            let context = ConnectionContext {
+                tx,
+                shutdown,
+                error_pages,
+                limits: options.limits,
+                tls_config,
+                // shared by all addresses
+                connections: options
+                    .max_connections
+                    .map(|max_connections| Arc::new(Semaphore::new(max_connections))),
+            };
 
-                    let mut threads = vec![];
-                    // listen for new connections
-                    while let Ok((socket, _)) = listener.accept().await {
-                        let tx = tx.clone();
-                        threads.push(tokio::spawn(async move {
-                            let mut tcp_connection = PersistentTcpConnection::new(socket).unwrap();
-                            while let Ok(message) = tcp_connection.messages().await {
-                                let req =
-                                    Request::new(message.content, tcp_connection.remote_addr())
-                                        .unwrap();
-
-                                let write_handle = tcp_connection.get_write_handle().clone();
-                                let res = Response::__new(
-                                    write_handle,
-                                    req.clone(),
-                                    true,
-                                    tcp_connection.get_message_count() == TCP_MAX_MESSAGES,
-                                );
-                                tx.send((req, res)).await;
-                            }
-                        }));
-                    }
-                    futures::future::join_all(threads).await;
-                }
-                CreeOptions::HttpsServer { .. } => {}
-            }
+            let listeners = listeners
+                .into_iter()
+                .map(|listener| tokio::spawn(listen_on(listener, context.clone())));
+            join_all(listeners).await;
         });
         self.http_listener_thread = Some(listener_thread);
         self.http_listener_receiver = Some(rx);
+        Ok(())
     }
 
-    pub async fn accept(&mut self) -> Result<(Request, Response), ()> {
+    /// Waits for the next request, fails once the listener stopped.
+    pub async fn accept(&mut self) -> Result<(Request, Response), Error> {
         if let Some(http_listener_receiver) = &mut self.http_listener_receiver {
-            return Ok(http_listener_receiver.recv().await.ok_or(())?);
+            return http_listener_receiver.recv().await.ok_or(Error::new(
+                "The listener was closed.",
+                TcpError::ListenerClosed,
+            ));
         }
-        Err(())
+        Err(Error::new(
+            "The server is not listening.",
+            TcpError::ListenerClosed,
+        ))
+    }
+
+    pub fn get<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.get(path, handler);
+        self
+    }
+    pub fn head<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.head(path, handler);
+        self
+    }
+    pub fn post<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.post(path, handler);
+        self
+    }
+    pub fn put<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.put(path, handler);
+        self
+    }
+    pub fn patch<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.patch(path, handler);
+        self
+    }
+    pub fn delete<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.delete(path, handler);
+        self
+    }
+    pub fn options<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.options(path, handler);
+        self
+    }
+    pub fn all<F, Fut>(&mut self, path: &str, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.all(path, handler);
+        self
     }
+    pub fn fallback<F, Fut>(&mut self, handler: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.fallback(handler);
+        self
+    }
+
+    /// Adds a middleware which runs before the routes, in the order it was added.
+    pub fn use_middleware<F, Fut>(&mut self, middleware: F) -> &mut CreeServer
+    where
+        F: Fn(Request, Response, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.router.use_middleware(middleware);
+        self
+    }
+
+    /// Dispatches incoming requests to the registered routes until the listener stops.
+    /// Routes have to be registered before calling this function.
+    ///
+    /// After a shutdown this function returns once the shutdown completed.
+    pub async fn serve(&mut self) {
+        let router = Arc::new(std::mem::take(&mut self.router));
+        while let Ok((req, res)) = self.accept().await {
+            let router = router.clone();
+            tokio::spawn(async move {
+                router.dispatch(req, res).await;
+            });
+        }
+        if self.shutdown.is_closing() {
+            self.shutdown.finished().await;
+        }
+    }
+
+    /// Sets the body sent with an error status the server answers with on its own: `400` for
+    /// unreadable requests, `404` without a matching route, `405` and `500` if a handler panicked
+    /// or returned without responding. Handlers can send the page with `Response::send_error`.
+    pub fn error_page(
+        &mut self,
+        status: HTTPStatus,
+        content_type: &str,
+        body: &[u8],
+    ) -> &mut CreeServer {
+        self.error_pages.set(&status, content_type, body);
+        self
+    }
+
+    /// A handle which can stop the server while it is serving requests.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Stops accepting connections and resolves once all in-flight responses were sent, see `ShutdownHandle`.
+    pub async fn shutdown(&self) {
+        self.shutdown.shutdown().await
+    }
+
+    /// Like `CreeServer::shutdown`, but gives up waiting for the remaining connections after the timeout.
+    pub async fn shutdown_with_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        self.shutdown.shutdown_with_timeout(timeout).await
+    }
+
+    /// Shuts the server down once the process receives SIGINT (Ctrl+C) or SIGTERM,
+    /// in-flight responses get up to `timeout` to finish.
+    pub fn shutdown_on_signal(&mut self, timeout: Duration) -> &mut CreeServer {
+        self.shutdown.on_signal(timeout);
+        self
+    }
+}
+
+// binds without waiting, like TcpListener::bind the address can be reused right after a restart
+fn bind(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(address)?;
+    socket.listen(1024)
+}
+
+// accepts connections until the server shuts down, then waits for the open ones to be drained
+async fn listen_on(listener: TcpListener, context: ConnectionContext) {
+    let mut shutdown = context.shutdown.clone();
+    let mut threads = vec![];
+    while let Some(socket) = accept(&listener, &mut shutdown).await {
+        threads.push(tokio::spawn(open_connection(socket, context.clone())));
+    }
+    drop(listener);
+    join_all(threads).await;
+}
+
+// waits for the next connection, None once the listener failed or the server shuts down
+async fn accept(listener: &TcpListener, shutdown: &mut ShutdownSignal) -> Option<TcpStream> {
+    tokio::select! {
+        result = listener.accept() => result.ok().map(|(socket, _)| socket),
+        _ = shutdown.closing() => None,
+    }
+}
+
+async fn open_connection(mut socket: TcpStream, context: ConnectionContext) {
+    // the permit is held until the connection is closed, it covers the TLS handshake as well
+    let _permit = match &context.connections {
+        Some(connections) => match connections.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                // an HTTPS client can't read a response before the handshake, it is only closed
+                if context.tls_config.is_none() {
+                    let response =
+                        context
+                            .error_pages
+                            .response(&HTTPStatus::ServiceUnavailable, true, true);
+                    let _ = socket.write_all(&response).await;
+                }
+                let _ = socket.shutdown().await;
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let limits = context.limits.clone();
+    let tcp_connection = match &context.tls_config {
+        // a failed or stalled handshake only drops this connection
+        Some(config) => match time::timeout(
+            limits.header_timeout,
+            TLSConnection::accept(socket, config.clone()),
+        )
+        .await
+        {
+            Ok(Ok(tls_connection)) => {
+                let remote_address = tls_connection.remote_addr();
+                let (reader, writer) = tls_connection.split();
+                PersistentTcpConnection::from_parts(
+                    remote_address,
+                    Box::new(reader),
+                    Box::new(writer),
+                    limits,
+                )
+            }
+            Ok(Err(_)) | Err(_) => return,
+        },
+        // the client may already be gone
+        None => match PersistentTcpConnection::new(socket, limits) {
+            Ok(tcp_connection) => tcp_connection,
+            Err(_) => return,
+        },
+    };
+    handle_connection(tcp_connection, context).await;
+}
+
+// reads requests from an established connection and passes them to the server
+async fn handle_connection(
+    mut tcp_connection: PersistentTcpConnection,
+    context: ConnectionContext,
+) {
+    let ConnectionContext {
+        tx,
+        mut shutdown,
+        error_pages,
+        ..
+    } = context;
+    // whether the last request asked to switch protocols
+    let mut upgrade = false;
+    loop {
+        let message = tokio::select! {
+            message = tcp_connection.messages() => message,
+            _ = shutdown.closing() => break,
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e @ Error::Http { .. }) => {
+                let index = tcp_connection.get_message_count();
+                reject_request(&tcp_connection, index, &e, &error_pages).await;
+                break;
+            }
+            Err(_) => break,
+        };
+        let mut req = match Request::new(message.content, tcp_connection.remote_addr()) {
+            Ok(req) => req,
+            Err(e) => {
+                let index = tcp_connection.get_message_count() - 1;
+                reject_request(&tcp_connection, index, &e, &error_pages).await;
+                break;
+            }
+        };
+        if let Some(body) = message.body {
+            req.set_body_stream(body);
+        }
+        upgrade = message.upgrade.is_some();
+        if let Some(upgrade) = message.upgrade {
+            req.set_upgrade(upgrade);
+        }
+
+        // responses are queued by the position of their request so pipelined requests are answered in order
+        let is_last = tcp_connection.is_last_message();
+        let response_queue = tcp_connection.get_response_queue().clone();
+        let res = Response::__new(
+            response_queue,
+            tcp_connection.get_message_count() - 1,
+            req.clone(),
+            is_last,
+            shutdown.clone(),
+            error_pages.clone(),
+        );
+        if tx.send((req, res)).await.is_err() || is_last {
+            break;
+        }
+    }
+
+    // no more requests are read, the connection is closed once the received ones were answered,
+    // an upgrade on the last request keeps it open (the response closes it if the upgrade is rejected)
+    let count = tcp_connection.get_message_count();
+    if shutdown.is_closing() || (tcp_connection.is_last_message() && !upgrade) {
+        let response_queue = tcp_connection.get_response_queue().clone();
+        let _ = response_queue.lock().await.close_after(count).await;
+    }
+}
+
+// answers a request which can't be read and closes the connection, the following data can't be trusted
+async fn reject_request(
+    tcp_connection: &PersistentTcpConnection,
+    index: u32,
+    error: &Error,
+    error_pages: &ErrorPages,
+) {
+    let status = match error.kind() {
+        ErrorKind::Http(HttpError::HeadTooLarge) => HTTPStatus::RequestHeaderFieldsTooLarge,
+        ErrorKind::Http(HttpError::Timeout) => HTTPStatus::RequestTimeout,
+        ErrorKind::Http(HttpError::BodyTooLarge) => HTTPStatus::ContentTooLarge,
+        _ => HTTPStatus::BadRequest,
+    };
+    let response = error_pages.response(&status, true, true);
+    let mut response_queue = tcp_connection.get_response_queue().lock().await;
+    let _ = response_queue.write(index, &response, true).await;
+    let _ = response_queue.close_after(index + 1).await;
 }
@@ -0,0 +1,388 @@
+use bytes::{Bytes, BytesMut};
+
+use super::decode_uri_component;
+use super::framing::MAX_HEAD_SIZE;
+use super::headers::HeaderMap;
+use super::protocol::Request;
+use crate::error::HttpError;
+use crate::Error;
+
+/// Ordered key-value pairs decoded from a query string or an urlencoded body, a key can repeat.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormData {
+    pairs: Vec<(String, String)>,
+}
+
+impl FormData {
+    /// Decodes `name=john+doe&age=21`, pairs can also be separated by line breaks.
+    pub fn parse(data: &str) -> FormData {
+        let pairs = data
+            .split(['&', '\n', '\r'])
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode_form_component(key), decode_form_component(value))
+            })
+            .collect();
+        FormData { pairs }
+    }
+
+    /// The first value of the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values of the key in the order they were sent.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+// `+` stands for a space in urlencoded data
+fn decode_form_component(value: &str) -> String {
+    decode_uri_component(&value.replace('+', " "))
+}
+
+/// Returns a parameter of a header value (ex.: `boundary` in `multipart/form-data; boundary=abc`).
+pub fn header_parameter(value: &str, name: &str) -> Option<String> {
+    let mut parameters = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => current.extend(chars.next()),
+            ';' if !quoted => parameters.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parameters.push(current);
+
+    parameters.iter().skip(1).find_map(|parameter| {
+        let (key, value) = parameter.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().to_owned())
+        } else {
+            None
+        }
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum MultipartState {
+    // before the next delimiter
+    Delimiter,
+    // inside the body of a part
+    Part,
+    Done,
+}
+
+/// A `multipart/form-data` body read part by part, see `Request::multipart`.
+///
+/// Parts are streamed, so large file uploads are never kept in memory as a whole.
+pub struct Multipart {
+    req: Request,
+    // "--" followed by the boundary
+    delimiter: Vec<u8>,
+    buffer: BytesMut,
+    state: MultipartState,
+    eof: bool,
+}
+
+impl Multipart {
+    pub(crate) fn new(req: Request, boundary: &str) -> Multipart {
+        Multipart {
+            req,
+            delimiter: [b"--", boundary.as_bytes()].concat(),
+            buffer: BytesMut::new(),
+            state: MultipartState::Delimiter,
+            eof: false,
+        }
+    }
+
+    /// Returns the next part, the rest of the previous part is skipped. Returns None after the last part.
+    pub async fn next_part(&mut self) -> Result<Option<Part<'_>>, Error> {
+        while self.state == MultipartState::Part {
+            self.read_part_chunk().await?;
+        }
+        if self.state == MultipartState::Done {
+            return Ok(None);
+        }
+
+        // the preamble before the first delimiter is ignored
+        let start = loop {
+            if let Some(position) = find(&self.buffer, &self.delimiter) {
+                break position;
+            }
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err(Error::new(
+                    "Multipart preamble is too large.",
+                    HttpError::InvalidRequest,
+                ));
+            }
+            self.fill().await?;
+        };
+        let _ = self.buffer.split_to(start + self.delimiter.len());
+
+        // "--" after the delimiter marks the end of the body
+        while self.buffer.len() < 2 {
+            self.fill().await?;
+        }
+        if &self.buffer[..2] == b"--" {
+            self.state = MultipartState::Done;
+            return Ok(None);
+        }
+
+        let head_end = loop {
+            if let Some(position) = find(&self.buffer, b"\r\n\r\n") {
+                break position;
+            }
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err(Error::new(
+                    "Multipart headers are too large.",
+                    HttpError::InvalidRequest,
+                ));
+            }
+            self.fill().await?;
+        };
+        let head = self.buffer.split_to(head_end + 4);
+        let head = String::from_utf8_lossy(&head);
+
+        // the first line is the rest of the delimiter line
+        let headers = HeaderMap::parse(head.split_once("\r\n").map_or("", |(_, lines)| lines));
+        let disposition = headers.get("content-disposition").unwrap_or("");
+        let name = header_parameter(disposition, "name").unwrap_or_default();
+        let filename = header_parameter(disposition, "filename");
+
+        self.state = MultipartState::Part;
+        Ok(Some(Part {
+            name,
+            filename,
+            headers,
+            multipart: self,
+        }))
+    }
+
+    // returns the next piece of the current part, None once the part ended
+    async fn read_part_chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        if self.state != MultipartState::Part {
+            return Ok(None);
+        }
+        let boundary = [b"\r\n", &self.delimiter[..]].concat();
+        loop {
+            if let Some(position) = find(&self.buffer, &boundary) {
+                let data = self.buffer.split_to(position).freeze();
+                // the delimiter is kept for next_part
+                let _ = self.buffer.split_to(2);
+                self.state = MultipartState::Delimiter;
+                return Ok(if data.is_empty() { None } else { Some(data) });
+            }
+            // everything which can't be the start of the delimiter is part data
+            if self.buffer.len() >= boundary.len() {
+                let length = self.buffer.len() - (boundary.len() - 1);
+                return Ok(Some(self.buffer.split_to(length).freeze()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), Error> {
+        if self.eof {
+            return Err(Error::new(
+                "Unexpected end of the multipart body.",
+                HttpError::InvalidRequest,
+            ));
+        }
+        match self.req.read_chunk().await? {
+            Some(chunk) => self.buffer.extend_from_slice(&chunk),
+            None => self.eof = true,
+        }
+        Ok(())
+    }
+}
+
+/// A single part of a multipart body, either a form field or a file.
+pub struct Part<'a> {
+    /// Name of the form field.
+    pub name: String,
+    /// Name of the uploaded file, None for plain fields.
+    pub filename: Option<String>,
+    pub headers: HeaderMap,
+    multipart: &'a mut Multipart,
+}
+
+impl<'a> Part<'a> {
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("content-type")
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// Reads the next piece of the part content, returns None once the whole part was read.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        self.multipart.read_part_chunk().await
+    }
+
+    /// Reads the whole part content.
+    pub async fn bytes(&mut self) -> Result<Bytes, Error> {
+        let mut content = BytesMut::new();
+        while let Some(chunk) = self.chunk().await? {
+            content.extend_from_slice(&chunk);
+        }
+        Ok(content.freeze())
+    }
+
+    /// Reads the whole part content as UTF-8 text.
+    pub async fn text(&mut self) -> Result<String, Error> {
+        String::from_utf8(self.bytes().await?.to_vec()).map_err(|e| {
+            Error::new("Part is not valid UTF-8.", HttpError::InvalidRequest).with_source(e)
+        })
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use tokio::sync::mpsc;
+
+    // a request whose body arrives in the given chunks
+    fn multipart(chunks: &[&[u8]]) -> Multipart {
+        let head =
+            b"POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"xyz\"\r\n\r\n";
+        let mut req = Request::new(head.to_vec(), "127.0.0.1:8080".parse().unwrap()).unwrap();
+        let (tx, rx) = mpsc::channel(chunks.len().max(1));
+        for chunk in chunks {
+            tx.try_send(Ok(Bytes::copy_from_slice(chunk))).unwrap();
+        }
+        req.set_body_stream(rx);
+        req.multipart().unwrap()
+    }
+
+    #[test]
+    fn form_data_decodes_percent_and_plus() {
+        let form = FormData::parse("name=john+doe&city=S%C3%A3o%20Paulo&empty=&flag");
+        assert_eq!(form.get("name"), Some("john doe"));
+        assert_eq!(form.get("city"), Some("São Paulo"));
+        assert_eq!(form.get("empty"), Some(""));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("missing"), None);
+    }
+
+    #[test]
+    fn form_data_keeps_repeated_keys() {
+        let form = FormData::parse("tag=a&other=x&tag=b\r\ntag=c");
+        assert_eq!(form.get("tag"), Some("a"));
+        assert_eq!(form.get_all("tag"), vec!["a", "b", "c"]);
+        assert_eq!(form.len(), 4);
+    }
+
+    #[test]
+    fn header_parameter_handles_quotes() {
+        let value = "form-data; name=\"file\"; filename=\"a;b \\\"c\\\".txt\"";
+        assert_eq!(header_parameter(value, "name").as_deref(), Some("file"));
+        assert_eq!(
+            header_parameter(value, "filename").as_deref(),
+            Some("a;b \"c\".txt")
+        );
+        assert_eq!(
+            header_parameter("multipart/form-data; Boundary=abc", "boundary").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(header_parameter("form-data", "name"), None);
+    }
+
+    #[tokio::test]
+    async fn multipart_reads_parts_across_chunks() {
+        let mut multipart = multipart(&[
+            b"preamble\r\n--xyz\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n--x",
+            b"yz\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"notes.txt\"\r\n",
+            b"Content-Type: text/plain\r\n\r\nfile content\r\n--xyz--\r\n",
+        ]);
+
+        let mut part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name, "title");
+        assert!(!part.is_file());
+        assert_eq!(part.text().await.unwrap(), "hello");
+
+        let mut part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name, "upload");
+        assert_eq!(part.filename.as_deref(), Some("notes.txt"));
+        assert_eq!(part.content_type(), Some("text/plain"));
+        assert_eq!(part.text().await.unwrap(), "file content");
+
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn multipart_reads_empty_part() {
+        let mut multipart = multipart(&[
+            b"--xyz\r\nContent-Disposition: form-data; name=\"empty\"\r\n\r\n\r\n--xyz--",
+        ]);
+
+        let mut part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name, "empty");
+        assert!(part.bytes().await.unwrap().is_empty());
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn multipart_fails_without_closing_delimiter() {
+        let mut multipart =
+            multipart(&[b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nunfinished"]);
+
+        let mut part = multipart.next_part().await.unwrap().unwrap();
+        let result = part.bytes().await;
+        assert!(matches!(
+            result.map_err(|e| e.kind()),
+            Err(ErrorKind::Http(HttpError::InvalidRequest))
+        ));
+    }
+
+    #[tokio::test]
+    async fn multipart_limits_preamble() {
+        let preamble = vec![b'a'; MAX_HEAD_SIZE + 1];
+        let mut multipart = multipart(&[&preamble, b"\r\n--xyz--"]);
+
+        let result = multipart.next_part().await.map(|part| part.is_some());
+        assert!(matches!(
+            result.map_err(|e| e.kind()),
+            Err(ErrorKind::Http(HttpError::InvalidRequest))
+        ));
+    }
+}
@@ -1,33 +1,121 @@
+pub const BAD_REQUEST: &str = "400 - Bad request";
 pub const NOT_FOUND: &str = "404 - Page not found";
+pub const METHOD_NOT_ALLOWED: &str = "405 - Method not allowed";
 pub const SERVER_ERROR: &str = "500 - Server error";
 
-#[derive(Debug)]
-pub enum HTTPStatus {
-    Ok,
-    Accepted,
-    NotFound,
-    ServerError,
-    MethodNotAllowed,
-    BadRequest,
-    Unauthorized,
-    Forbidden,
-    NoContent,
-    PartialContent,
-    RangeNotSatisfiable,
+// generates the status enum together with the code and reason phrase of each variant
+macro_rules! http_statuses {
+    ($($variant:ident = $code:literal, $phrase:literal;)*) => {
+        /// Status codes of the IANA HTTP status code registry, `Custom` can send any other code.
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum HTTPStatus {
+            $($variant,)*
+            /// Any three digit status code with a custom reason phrase.
+            Custom(u16, String),
+        }
+
+        impl HTTPStatus {
+            /// Returns the registered status for the code, None for unregistered codes.
+            pub fn from_code(code: u16) -> Option<HTTPStatus> {
+                match code {
+                    $($code => Some(HTTPStatus::$variant),)*
+                    _ => None,
+                }
+            }
+
+            /// The numeric status code.
+            pub fn code(&self) -> u16 {
+                match self {
+                    $(HTTPStatus::$variant => $code,)*
+                    HTTPStatus::Custom(code, _) => *code,
+                }
+            }
+        }
+
+        pub fn get_phrase_from_code(status: &HTTPStatus) -> Option<(u16, String)> {
+            match status {
+                $(HTTPStatus::$variant => Some(($code, String::from($phrase))),)*
+                HTTPStatus::Custom(code, phrase) => {
+                    // the phrase is written to the status line as it is
+                    if !(100..=999).contains(code) || phrase.contains(['\r', '\n']) {
+                        return None;
+                    }
+                    Some((*code, phrase.clone()))
+                }
+            }
+        }
+    };
 }
 
-pub fn get_phrase_from_code(status: &HTTPStatus) -> Option<(u16, String)> {
-    match status {
-        HTTPStatus::Ok => Some((200, String::from("OK"))),
-        HTTPStatus::Accepted => Some((202, String::from("ACCEPTED"))),
-        HTTPStatus::NoContent => Some((204, String::from("NO_CONTENT"))),
-        HTTPStatus::PartialContent => Some((206, String::from("PARTIAL_CONTENT"))),
-        HTTPStatus::BadRequest => Some((400, String::from("BAD_REQUEST"))),
-        HTTPStatus::NotFound => Some((404, String::from("NOT_FOUND"))),
-        HTTPStatus::MethodNotAllowed => Some((405, String::from("METHOD_NOT_ALLOWED"))),
-        HTTPStatus::Unauthorized => Some((401, String::from("UNAUTHORIZED"))),
-        HTTPStatus::Forbidden => Some((403, String::from("FORBIDDEN"))),
-        HTTPStatus::RangeNotSatisfiable => Some((416, String::from("RANGE_NOT_SATISFIABLE"))),
-        HTTPStatus::ServerError => Some((500, String::from("SERVER_ERROR"))),
-    }
+http_statuses! {
+    // 1xx informational
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Processing = 102, "Processing";
+    EarlyHints = 103, "Early Hints";
+
+    // 2xx success
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultiStatus = 207, "Multi-Status";
+    AlreadyReported = 208, "Already Reported";
+    ImUsed = 226, "IM Used";
+
+    // 3xx redirection
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+
+    // 4xx client errors
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    Locked = 423, "Locked";
+    FailedDependency = 424, "Failed Dependency";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+
+    // 5xx server errors
+    ServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+    InsufficientStorage = 507, "Insufficient Storage";
+    LoopDetected = 508, "Loop Detected";
+    NotExtended = 510, "Not Extended";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
 }
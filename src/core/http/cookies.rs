@@ -0,0 +1,450 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use crypto::mac::{Mac, MacResult};
+use rand_core::{OsRng, RngCore};
+
+use super::format_http_date;
+use super::headers::is_token;
+use crate::core::tls::crypto::EncryptedMessage;
+use crate::core::tls::digest::HmacSha256;
+use crate::error::HttpError;
+use crate::Error;
+
+// AES-GCM nonce + authentication tag
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// Cookies sent by the client in the `Cookie` header.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    /// Parses a `Cookie` header value (ex.: `name=value; theme=dark`).
+    pub fn parse(header: &str) -> CookieJar {
+        let cookies = header
+            .split(';')
+            .filter_map(|cookie| {
+                let (name, value) = cookie.split_once('=')?;
+                let name = name.trim();
+                if name.is_empty() {
+                    return None;
+                }
+                // values can be wrapped in double quotes
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.to_owned(), value.to_owned()))
+            })
+            .collect();
+        CookieJar { cookies }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the value of a cookie created with `Cookie::signed`, None if the signature doesn't match.
+    pub fn get_signed(&self, name: &str, key: &CookieKey) -> Option<String> {
+        key.verify(name, self.get(name)?)
+    }
+
+    /// Returns the value of a cookie created with `Cookie::encrypted`, None if it can't be decrypted.
+    pub fn get_encrypted(&self, name: &str, key: &CookieKey) -> Option<String> {
+        key.decrypt(name, self.get(name)?)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.cookies.iter().any(|(key, _)| key == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie sent to the client with `Response::set_cookie`, attributes are set with the builder methods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub expires: Option<DateTime<Utc>>,
+    pub max_age: Option<Duration>,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// The name has to be a token and the value may only contain cookie octets (RFC 6265 4.1.1),
+    /// so it can't add attributes or headers.
+    pub fn new(name: &str, value: &str) -> Result<Cookie, Error> {
+        validate_name(name)?;
+        validate_value(value)?;
+        Ok(Cookie {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// A cookie whose value is signed with the key, read it with `CookieJar::get_signed`.
+    /// The value stays readable by the client but can't be changed.
+    pub fn signed(name: &str, value: &str, key: &CookieKey) -> Result<Cookie, Error> {
+        validate_value(value)?;
+        Cookie::new(name, &key.sign(name, value))
+    }
+
+    /// A cookie whose value is encrypted with the key, read it with `CookieJar::get_encrypted`.
+    /// The value itself can contain any characters.
+    pub fn encrypted(name: &str, value: &str, key: &CookieKey) -> Result<Cookie, Error> {
+        validate_name(name)?;
+        Cookie::new(name, &key.encrypt(name, value))
+    }
+
+    pub fn expires(mut self, expires: DateTime<Utc>) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(path.to_owned());
+        self
+    }
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Makes the client delete the cookie, the path and domain have to match the original cookie.
+    pub fn expire(mut self) -> Cookie {
+        self.value = String::new();
+        self.max_age = Some(Duration::zero());
+        self.expires = Some(DateTime::from(std::time::UNIX_EPOCH));
+        self
+    }
+
+    /// Checks every part written into the `Set-Cookie` header, used by `Response::set_cookie`.
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_name(&self.name)?;
+        validate_value(&self.value)?;
+        for attribute in [&self.domain, &self.path].into_iter().flatten() {
+            // any character but controls and `;` (RFC 6265 4.1.1)
+            if attribute
+                .bytes()
+                .any(|c| !(0x20..0x7F).contains(&c) || c == b';')
+            {
+                return Err(invalid_cookie("Invalid cookie attribute."));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || !name.bytes().all(is_token) {
+        return Err(invalid_cookie("Invalid cookie name."));
+    }
+    Ok(())
+}
+
+fn validate_value(value: &str) -> Result<(), Error> {
+    // the value can be wrapped in double quotes
+    let unquoted = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    if !unquoted.bytes().all(is_cookie_octet) {
+        return Err(invalid_cookie("Invalid cookie value."));
+    }
+    Ok(())
+}
+
+fn invalid_cookie(message: &str) -> Error {
+    Error::new(message, HttpError::InvalidCookie)
+}
+
+// printable ASCII except whitespace, `"`, `,`, `;` and `\`
+fn is_cookie_octet(c: u8) -> bool {
+    matches!(c, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+/// Formats the cookie as a `Set-Cookie` header value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(expires) = &self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        if let Some(max_age) = &self.max_age {
+            write!(f, "; Max-Age={}", max_age.num_seconds())?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        // browsers reject SameSite=None without Secure
+        if self.secure || self.same_site == Some(SameSite::None) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = &self.same_site {
+            write!(f, "; SameSite={:?}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Secret used to sign and encrypt cookies.
+///
+/// Separate HMAC-SHA256 and AES-128-GCM keys are derived from the secret, which should be
+/// at least 32 random bytes and stay the same between restarts.
+#[derive(Clone)]
+pub struct CookieKey {
+    signing_key: Vec<u8>,
+    encryption_key: Vec<u8>,
+}
+
+impl CookieKey {
+    pub fn new(secret: &[u8]) -> CookieKey {
+        CookieKey {
+            signing_key: hmac(secret, b"cookie signing"),
+            encryption_key: hmac(secret, b"cookie encryption")[..16].to_vec(),
+        }
+    }
+
+    // the name is signed as well, so a value can't be moved to another cookie
+    fn sign(&self, name: &str, value: &str) -> String {
+        let signature = hmac(&self.signing_key, format!("{}={}", name, value).as_bytes());
+        format!("{}.{}", value, encode(&signature))
+    }
+
+    fn verify(&self, name: &str, signed_value: &str) -> Option<String> {
+        let (value, signature) = signed_value.rsplit_once('.')?;
+        let signature = decode(signature)?;
+        let expected = hmac(&self.signing_key, format!("{}={}", name, value).as_bytes());
+        // MacResult compares in constant time
+        if MacResult::new(&expected) != MacResult::new(&signature) {
+            return None;
+        }
+        Some(value.to_owned())
+    }
+
+    fn encrypt(&self, name: &str, value: &str) -> String {
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        let encrypted = EncryptedMessage::encrypt(
+            value.as_bytes(),
+            &nonce,
+            &self.encryption_key,
+            name.as_bytes(),
+        );
+        encode(&[&nonce[..], &encrypted[..]].concat())
+    }
+
+    fn decrypt(&self, name: &str, encrypted_value: &str) -> Option<String> {
+        let data = decode(encrypted_value)?;
+        if data.len() < NONCE_SIZE + TAG_SIZE {
+            return None;
+        }
+        let (nonce, encrypted) = data.split_at(NONCE_SIZE);
+        let value =
+            EncryptedMessage::decrypt(encrypted, nonce, &self.encryption_key, name.as_bytes())
+                .ok()?;
+        String::from_utf8(value).ok()
+    }
+}
+
+impl fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieKey").finish()
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new(key);
+    mac.input(data);
+    mac.result().code().to_vec()
+}
+
+// cookie values can't contain `+`, `/` or `=` without quoting
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(data: &str) -> Option<Vec<u8>> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn key() -> CookieKey {
+        CookieKey::new(b"a secret which is at least 32 bytes long")
+    }
+
+    fn is_invalid(result: Result<Cookie, Error>) -> bool {
+        matches!(
+            result.map_err(|e| e.kind()),
+            Err(ErrorKind::Http(HttpError::InvalidCookie))
+        )
+    }
+
+    #[test]
+    fn parse_cookie_header() {
+        let jar = CookieJar::parse("name=value; theme=\"dark\";empty=; =nameless; invalid");
+        assert_eq!(jar.get("name"), Some("value"));
+        assert_eq!(jar.get("theme"), Some("dark"));
+        assert_eq!(jar.get("empty"), Some(""));
+        assert_eq!(jar.len(), 3);
+        assert!(!jar.contains("invalid"));
+    }
+
+    #[test]
+    fn serialize_attributes() {
+        let cookie = Cookie::new("id", "abc")
+            .unwrap()
+            .expires(DateTime::from(std::time::UNIX_EPOCH))
+            .max_age(Duration::seconds(60))
+            .domain("example.com")
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "id=abc; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=60; Domain=example.com; \
+             Path=/; HttpOnly; SameSite=Lax"
+        );
+
+        // SameSite=None requires Secure
+        let cookie = Cookie::new("id", "abc").unwrap().same_site(SameSite::None);
+        assert_eq!(cookie.to_string(), "id=abc; Secure; SameSite=None");
+    }
+
+    #[test]
+    fn reject_invalid_names_and_values() {
+        for name in ["", "a b", "a;b", "a=b", "a\r\nb", "a,b", "ä"] {
+            assert!(is_invalid(Cookie::new(name, "value")), "{:?}", name);
+        }
+        for value in [
+            "a b",
+            "a;Domain=evil",
+            "a,b",
+            "a\r\nSet-Cookie: x=y",
+            "a\"b",
+            "a\\b",
+        ] {
+            assert!(is_invalid(Cookie::new("name", value)), "{:?}", value);
+        }
+        assert!(Cookie::new("name", "\"quoted\"").is_ok());
+        assert!(Cookie::new("name", "").is_ok());
+
+        // attributes are checked before the cookie is set
+        let cookie = Cookie::new("name", "value").unwrap();
+        assert!(cookie.clone().path("/; Domain=evil").validate().is_err());
+        assert!(cookie.clone().domain("evil\r\nX: y").validate().is_err());
+        assert!(cookie.path("/app").domain("example.com").validate().is_ok());
+    }
+
+    #[test]
+    fn signed_round_trip() {
+        let key = key();
+        let cookie = Cookie::signed("user", "john", &key).unwrap();
+        let jar = CookieJar::parse(&format!("user={}", cookie.value));
+        assert_eq!(jar.get_signed("user", &key), Some(String::from("john")));
+
+        // a changed value, signature or key doesn't verify
+        let (_, signature) = cookie.value.rsplit_once('.').unwrap();
+        let jar = CookieJar::parse(&format!("user=admin.{}", signature));
+        assert_eq!(jar.get_signed("user", &key), None);
+        let first = if signature.starts_with('A') { 'B' } else { 'A' };
+        let tampered = format!("john.{}{}", first, &signature[1..]);
+        let jar = CookieJar::parse(&format!("user={}", tampered));
+        assert_eq!(jar.get_signed("user", &key), None);
+        let jar = CookieJar::parse(&format!("user={}", cookie.value));
+        assert_eq!(
+            jar.get_signed("user", &CookieKey::new(b"another secret")),
+            None
+        );
+
+        // the signature is bound to the name
+        let jar = CookieJar::parse(&format!("admin={}", cookie.value));
+        assert_eq!(jar.get_signed("admin", &key), None);
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let key = key();
+        let cookie = Cookie::encrypted("secret", "42; any value", &key).unwrap();
+        assert!(!cookie.value.contains("42"));
+        let jar = CookieJar::parse(&format!("secret={}", cookie.value));
+        assert_eq!(
+            jar.get_encrypted("secret", &key),
+            Some(String::from("42; any value"))
+        );
+
+        // the name is the additional data, the value can't be moved to another cookie
+        let jar = CookieJar::parse(&format!("other={}", cookie.value));
+        assert_eq!(jar.get_encrypted("other", &key), None);
+
+        let mut data = decode(&cookie.value).unwrap();
+        data[NONCE_SIZE] ^= 1;
+        let jar = CookieJar::parse(&format!("secret={}", encode(&data)));
+        assert_eq!(jar.get_encrypted("secret", &key), None);
+        let jar = CookieJar::parse("secret=short");
+        assert_eq!(jar.get_encrypted("secret", &key), None);
+    }
+}
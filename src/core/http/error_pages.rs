@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::Utc;
+
+use super::codes::{
+    get_phrase_from_code, HTTPStatus, BAD_REQUEST, METHOD_NOT_ALLOWED, NOT_FOUND, SERVER_ERROR,
+};
+use super::format_http_date;
+
+#[derive(Debug, Clone)]
+struct ErrorPage {
+    content_type: String,
+    body: Vec<u8>,
+}
+
+/// Bodies of the error responses the server sends on its own (ex.: 404 without a matching route,
+/// 500 after a handler panicked), a short plain text message is sent for statuses without a page.
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    pages: RwLock<HashMap<u16, ErrorPage>>,
+}
+
+impl ErrorPages {
+    pub fn new() -> ErrorPages {
+        ErrorPages::default()
+    }
+
+    pub fn set(&self, status: &HTTPStatus, content_type: &str, body: &[u8]) {
+        let page = ErrorPage {
+            content_type: content_type.to_owned(),
+            body: body.to_vec(),
+        };
+        self.pages
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(status.code(), page);
+    }
+
+    /// Returns the content type and body sent with the status.
+    pub fn get(&self, status: &HTTPStatus) -> (String, Vec<u8>) {
+        let pages = self.pages.read().unwrap_or_else(|e| e.into_inner());
+        match pages.get(&status.code()) {
+            Some(page) => (page.content_type.clone(), page.body.clone()),
+            None => (
+                String::from("text/plain"),
+                default_message(status).into_bytes(),
+            ),
+        }
+    }
+
+    /// A complete error response, used when there is no `Response` to send it with.
+    pub(crate) fn response(&self, status: &HTTPStatus, with_body: bool, close: bool) -> Vec<u8> {
+        let (content_type, body) = self.get(status);
+        let (code, phrase) = get_phrase_from_code(status)
+            .unwrap_or_else(|| (500, String::from("Internal Server Error")));
+        let connection = if close { "close" } else { "keep-alive" };
+        let mut response = format!(
+            "HTTP/1.1 {} {}\r\nDate: {}\r\nConnection: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            code,
+            phrase,
+            format_http_date(&Utc::now()),
+            connection,
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        if with_body {
+            response.extend(body);
+        }
+        response
+    }
+}
+
+fn default_message(status: &HTTPStatus) -> String {
+    match status {
+        HTTPStatus::BadRequest => BAD_REQUEST.to_owned(),
+        HTTPStatus::NotFound => NOT_FOUND.to_owned(),
+        HTTPStatus::MethodNotAllowed => METHOD_NOT_ALLOWED.to_owned(),
+        HTTPStatus::ServerError => SERVER_ERROR.to_owned(),
+        _ => match get_phrase_from_code(status) {
+            Some((code, phrase)) => format!("{} - {}", code, phrase),
+            None => SERVER_ERROR.to_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_uses_crlf() {
+        let pages = ErrorPages::new();
+        pages.set(&HTTPStatus::NotFound, "text/html", b"<h1>Gone</h1>");
+
+        let response = pages.response(&HTTPStatus::NotFound, true, true);
+        let response = String::from_utf8(response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(body, "<h1>Gone</h1>");
+        assert!(head.starts_with("HTTP/1.1 404 "));
+        assert!(head.lines().all(|line| !line.is_empty()));
+        assert_eq!(head.matches("\r\n").count(), head.lines().count() - 1);
+        assert!(head.contains("\r\nConnection: close\r\n"));
+        assert!(head.ends_with("Content-Length: 13"));
+
+        let response = pages.response(&HTTPStatus::NotFound, false, false);
+        assert!(response.ends_with(b"Content-Length: 13\r\n\r\n"));
+    }
+}
@@ -0,0 +1,535 @@
+use super::headers::HeaderMap;
+use crate::error::HttpError;
+use crate::{Error, M_BYTE};
+
+/// Default upper limit of the request line and headers, a longer head is rejected.
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Longest chunk size line, chunk extensions included.
+const MAX_CHUNK_LINE: usize = 4096;
+
+/// Bodies up to this size are buffered and handed over together with the head,
+/// larger bodies are streamed in chunks.
+pub const MAX_BUFFERED_BODY: usize = M_BYTE;
+
+#[derive(Debug)]
+pub enum Frame {
+    /// A complete message, the head followed by the whole (decoded) body.
+    Message(Vec<u8>),
+    /// The head of a message whose body is streamed.
+    Head(Vec<u8>),
+    /// A part of a streamed body.
+    Body(Vec<u8>),
+    /// End of a streamed body.
+    End,
+}
+
+#[derive(Debug)]
+enum BodyState {
+    Length(usize),
+    ChunkSize,
+    ChunkData(usize),
+    ChunkDataEnd,
+    Trailers,
+    Done,
+}
+
+/// Splits a byte stream into HTTP messages.
+///
+/// The head ends with an empty line (`\r\n\r\n` or `\n\n`), the body length is then
+/// determined by the `Transfer-Encoding: chunked` or `Content-Length` headers. Chunked
+/// bodies are decoded, so the body frames always contain the raw body.
+pub struct MessageDecoder {
+    head: Option<Vec<u8>>,
+    body: Vec<u8>,
+    body_state: BodyState,
+    streaming: bool,
+    // size of the decoded body so far, streamed parts included
+    body_size: usize,
+    // trailers count against the head size
+    trailers_size: usize,
+    // the part of the buffer already searched for the end of the head
+    head_scanned: usize,
+    max_head_size: usize,
+    max_body_size: Option<usize>,
+}
+
+impl MessageDecoder {
+    pub fn new() -> MessageDecoder {
+        MessageDecoder::with_limits(MAX_HEAD_SIZE, None)
+    }
+
+    /// A decoder which rejects larger heads and bodies, None accepts bodies of any size.
+    pub fn with_limits(max_head_size: usize, max_body_size: Option<usize>) -> MessageDecoder {
+        MessageDecoder {
+            head: None,
+            body: vec![],
+            body_state: BodyState::Done,
+            streaming: false,
+            body_size: 0,
+            trailers_size: 0,
+            head_scanned: 0,
+            max_head_size,
+            max_body_size,
+        }
+    }
+
+    /// Whether the head of the current message was decoded and its body is being received.
+    pub fn is_reading_body(&self) -> bool {
+        self.head.is_some()
+    }
+
+    /// Consumes data from the buffer and returns the next frame, returns None if more data is needed.
+    pub fn decode(&mut self, buffer: &mut Vec<u8>) -> Result<Option<Frame>, Error> {
+        if self.head.is_none() {
+            match self.decode_head(buffer)? {
+                Some(head) => self.head = Some(head),
+                None => return Ok(None),
+            }
+        }
+        self.decode_body(buffer)?;
+
+        if let BodyState::Done = self.body_state {
+            if !self.streaming {
+                let mut message = self.head.take().unwrap_or_default();
+                message.extend(std::mem::take(&mut self.body));
+                return Ok(Some(Frame::Message(message)));
+            }
+            if self.body.is_empty() {
+                self.head = None;
+                self.streaming = false;
+                return Ok(Some(Frame::End));
+            }
+        } else if !self.streaming && self.body.len() > MAX_BUFFERED_BODY {
+            self.streaming = true;
+            return Ok(self.head.clone().map(Frame::Head));
+        }
+
+        if self.streaming && !self.body.is_empty() {
+            return Ok(Some(Frame::Body(std::mem::take(&mut self.body))));
+        }
+        Ok(None)
+    }
+
+    fn decode_head(&mut self, buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        // empty lines before the request line are ignored
+        let leading = buffer
+            .iter()
+            .take_while(|&&b| b == b'\r' || b == b'\n')
+            .count();
+        buffer.drain(..leading);
+
+        // the empty line may have been cut between the last two reads, its first newline is rescanned
+        let start = self.head_scanned.saturating_sub(leading).min(buffer.len());
+        let head_end = match find_head_end_from(buffer, start.saturating_sub(2)) {
+            Some(head_end) if head_end > self.max_head_size => {
+                return Err(Error::new(
+                    "Request head is too large.",
+                    HttpError::HeadTooLarge,
+                ));
+            }
+            Some(head_end) => head_end,
+            None => {
+                self.head_scanned = buffer.len();
+                if buffer.len() > self.max_head_size {
+                    return Err(Error::new(
+                        "Request head is too large.",
+                        HttpError::HeadTooLarge,
+                    ));
+                }
+                return Ok(None);
+            }
+        };
+
+        self.head_scanned = 0;
+        let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+        let headers = HeaderMap::parse(head.split_once('\n').map_or("", |(_, lines)| lines));
+
+        // chunked has to be the last applied encoding, otherwise the body length is unknown (RFC 9112, section 6.3)
+        let transfer_encoding = headers.get_joined("transfer-encoding");
+        if let Some(codings) = &transfer_encoding {
+            let chunked = codings
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+            if !chunked {
+                return Err(Error::new(
+                    "Transfer-Encoding does not end with chunked.",
+                    HttpError::InvalidRequest,
+                ));
+            }
+            // the two headers could be read differently by a proxy in front of the server
+            if headers.contains("content-length") {
+                return Err(Error::new(
+                    "Transfer-Encoding and Content-Length must not be combined.",
+                    HttpError::InvalidRequest,
+                ));
+            }
+        }
+        // repeated Content-Length headers have to agree
+        let mut content_length: Option<usize> = None;
+        for value in headers.get_all("content-length") {
+            let length = value.parse::<usize>().map_err(|e| {
+                Error::new("Invalid Content-Length header.", HttpError::InvalidRequest)
+                    .with_source(e)
+            })?;
+            if content_length.is_some_and(|previous| previous != length) {
+                return Err(Error::new(
+                    "Invalid Content-Length header.",
+                    HttpError::InvalidRequest,
+                ));
+            }
+            content_length = Some(length);
+        }
+        self.body_size = 0;
+        self.trailers_size = 0;
+        if content_length.is_some_and(|length| self.exceeds_body_size(length)) {
+            return Err(Error::new(
+                "Request body is too large.",
+                HttpError::BodyTooLarge,
+            ));
+        }
+
+        self.body_state = if transfer_encoding.is_some() {
+            BodyState::ChunkSize
+        } else {
+            match content_length {
+                Some(length) if length > 0 => BodyState::Length(length),
+                _ => BodyState::Done,
+            }
+        };
+        Ok(Some(buffer.drain(..head_end).collect()))
+    }
+
+    // moves as much of the body as possible from the buffer to the decoded body
+    fn decode_body(&mut self, buffer: &mut Vec<u8>) -> Result<(), Error> {
+        loop {
+            match self.body_state {
+                BodyState::Length(remaining) | BodyState::ChunkData(remaining) => {
+                    let length = remaining.min(buffer.len());
+                    self.body.extend(buffer.drain(..length));
+                    self.body_size += length;
+                    let remaining = remaining - length;
+
+                    self.body_state = match (&self.body_state, remaining) {
+                        (BodyState::Length(_), 0) => BodyState::Done,
+                        (BodyState::Length(_), _) => BodyState::Length(remaining),
+                        (_, 0) => BodyState::ChunkDataEnd,
+                        (_, _) => BodyState::ChunkData(remaining),
+                    };
+                    if remaining > 0 {
+                        return Ok(());
+                    }
+                }
+                BodyState::ChunkSize => {
+                    let line = match read_line(buffer, MAX_CHUNK_LINE)? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    };
+                    // chunk extensions (;name=value) are ignored
+                    let size = String::from_utf8_lossy(&line);
+                    let size = size.split(';').next().unwrap_or("").trim();
+                    let size = usize::from_str_radix(size, 16).map_err(|e| {
+                        Error::new("Invalid chunk size.", HttpError::InvalidRequest).with_source(e)
+                    })?;
+                    if self.exceeds_body_size(size) {
+                        return Err(Error::new(
+                            "Request body is too large.",
+                            HttpError::BodyTooLarge,
+                        ));
+                    }
+
+                    self.body_state = if size == 0 {
+                        BodyState::Trailers
+                    } else {
+                        BodyState::ChunkData(size)
+                    };
+                }
+                BodyState::ChunkDataEnd => match read_line(buffer, MAX_CHUNK_LINE)? {
+                    Some(line) if line.is_empty() => self.body_state = BodyState::ChunkSize,
+                    Some(_) => return Err(Error::new("Invalid chunk.", HttpError::InvalidRequest)),
+                    None => return Ok(()),
+                },
+                // trailers are skipped until the final empty line
+                BodyState::Trailers => {
+                    let remaining = self.max_head_size.saturating_sub(self.trailers_size);
+                    let line = match read_line(buffer, remaining) {
+                        Ok(Some(line)) => line,
+                        Ok(None) => return Ok(()),
+                        Err(_) => {
+                            return Err(Error::new(
+                                "Request trailers are too large.",
+                                HttpError::HeadTooLarge,
+                            ))
+                        }
+                    };
+                    if line.is_empty() {
+                        self.body_state = BodyState::Done;
+                    }
+                    // the line ending counts as well
+                    self.trailers_size += line.len() + 2;
+                }
+                BodyState::Done => return Ok(()),
+            }
+        }
+    }
+
+    // whether receiving this many more bytes would exceed the maximum body size
+    fn exceeds_body_size(&self, length: usize) -> bool {
+        self.max_body_size
+            .is_some_and(|max_body_size| self.body_size.saturating_add(length) > max_body_size)
+    }
+}
+
+impl Default for MessageDecoder {
+    fn default() -> MessageDecoder {
+        MessageDecoder::new()
+    }
+}
+
+/// Whether the message asks to switch to another protocol (`Connection: upgrade` and an `Upgrade` header).
+pub fn is_upgrade_request(message: &[u8]) -> bool {
+    let head_end = find_head_end(message).unwrap_or(message.len());
+    let head = String::from_utf8_lossy(&message[..head_end]);
+    let headers = HeaderMap::parse(head.split_once('\n').map_or("", |(_, lines)| lines));
+    headers.contains("upgrade")
+        && headers.get_joined("connection").is_some_and(|connection| {
+            connection
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        })
+}
+
+/// Returns the index right after the empty line ending the head.
+pub fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    find_head_end_from(buffer, 0)
+}
+
+fn find_head_end_from(buffer: &[u8], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < buffer.len() {
+        if buffer[i] == b'\n' {
+            match buffer.get(i + 1) {
+                Some(b'\n') => return Some(i + 2),
+                Some(b'\r') if buffer.get(i + 2) == Some(&b'\n') => return Some(i + 3),
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+// takes a single line out of the buffer, without its line ending, fails once the line is longer than max_length
+fn read_line(buffer: &mut Vec<u8>, max_length: usize) -> Result<Option<Vec<u8>>, Error> {
+    let too_long = || Error::new("Line is too long.", HttpError::InvalidRequest);
+    let end = match buffer.iter().take(max_length + 2).position(|&b| b == b'\n') {
+        Some(end) => end,
+        None if buffer.len() > max_length + 1 => return Err(too_long()),
+        None => return Ok(None),
+    };
+    let mut line: Vec<u8> = buffer.drain(..=end).collect();
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > max_length {
+        return Err(too_long());
+    }
+    Ok(Some(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    const HEAD: &[u8] = b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\n";
+    const CHUNKED_HEAD: &[u8] =
+        b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+    // decodes frames until more data is needed
+    fn decode_all(decoder: &mut MessageDecoder, buffer: &mut Vec<u8>) -> Result<Vec<Frame>, Error> {
+        let mut frames = vec![];
+        while let Some(frame) = decoder.decode(buffer)? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    fn error_kind(data: &[u8], decoder: &mut MessageDecoder) -> Option<ErrorKind> {
+        decode_all(decoder, &mut data.to_vec())
+            .err()
+            .map(|e| e.kind())
+    }
+
+    fn message(frames: &[Frame]) -> &[u8] {
+        match frames {
+            [Frame::Message(message)] => message,
+            _ => panic!("expected one message, got {:?}", frames),
+        }
+    }
+
+    #[test]
+    fn split_reads() {
+        let data = [HEAD, b"hello"].concat();
+        let mut decoder = MessageDecoder::new();
+        let mut buffer = vec![];
+        let mut frames = vec![];
+        // one byte per read, the empty line ending the head is split as well
+        for byte in &data {
+            buffer.push(*byte);
+            frames.extend(decode_all(&mut decoder, &mut buffer).unwrap());
+        }
+        assert_eq!(message(&frames), &data[..]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn head_split_after_first_newline() {
+        let mut decoder = MessageDecoder::new();
+        let mut buffer = b"GET / HTTP/1.1\nHost: localhost\n".to_vec();
+        assert!(decode_all(&mut decoder, &mut buffer).unwrap().is_empty());
+        buffer.extend(b"\n");
+        let frames = decode_all(&mut decoder, &mut buffer).unwrap();
+        assert_eq!(message(&frames), b"GET / HTTP/1.1\nHost: localhost\n\n");
+    }
+
+    #[test]
+    fn chunked_with_extensions_and_trailers() {
+        let body =
+            b"3;name=value\r\nabc\r\n2;flag\r\nde\r\n0\r\nX-Checksum: 1\r\nX-Other: 2\r\n\r\n";
+        let mut decoder = MessageDecoder::new();
+        let mut buffer = [CHUNKED_HEAD, body].concat();
+        let frames = decode_all(&mut decoder, &mut buffer).unwrap();
+        assert_eq!(message(&frames), &[CHUNKED_HEAD, b"abcde"].concat()[..]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn conflicting_content_length() {
+        let head = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd";
+        assert_eq!(
+            error_kind(head, &mut MessageDecoder::new()),
+            Some(ErrorKind::Http(HttpError::InvalidRequest))
+        );
+
+        // repeated but equal values are accepted
+        let head = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(error_kind(head, &mut MessageDecoder::new()), None);
+    }
+
+    #[test]
+    fn transfer_encoding_without_final_chunked() {
+        let head = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(
+            error_kind(head, &mut MessageDecoder::new()),
+            Some(ErrorKind::Http(HttpError::InvalidRequest))
+        );
+        let head = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n0\r\n\r\n";
+        assert_eq!(
+            error_kind(head, &mut MessageDecoder::new()),
+            Some(ErrorKind::Http(HttpError::InvalidRequest))
+        );
+    }
+
+    #[test]
+    fn transfer_encoding_with_content_length() {
+        let head =
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n0\r\n\r\n";
+        assert_eq!(
+            error_kind(head, &mut MessageDecoder::new()),
+            Some(ErrorKind::Http(HttpError::InvalidRequest))
+        );
+    }
+
+    #[test]
+    fn oversize_head() {
+        let mut decoder = MessageDecoder::with_limits(32, None);
+        assert_eq!(
+            error_kind(HEAD, &mut decoder),
+            Some(ErrorKind::Http(HttpError::HeadTooLarge))
+        );
+
+        // the head doesn't end before the limit
+        let mut decoder = MessageDecoder::with_limits(32, None);
+        assert_eq!(
+            error_kind(&[b'a'; 64], &mut decoder),
+            Some(ErrorKind::Http(HttpError::HeadTooLarge))
+        );
+    }
+
+    #[test]
+    fn oversize_body() {
+        let mut decoder = MessageDecoder::with_limits(MAX_HEAD_SIZE, Some(4));
+        assert_eq!(
+            error_kind(HEAD, &mut decoder),
+            Some(ErrorKind::Http(HttpError::BodyTooLarge))
+        );
+
+        let body = b"3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n";
+        let mut decoder = MessageDecoder::with_limits(MAX_HEAD_SIZE, Some(4));
+        assert_eq!(
+            error_kind(&[CHUNKED_HEAD, body].concat(), &mut decoder),
+            Some(ErrorKind::Http(HttpError::BodyTooLarge))
+        );
+    }
+
+    #[test]
+    fn long_chunk_lines() {
+        // a chunk size line which never ends
+        let mut data = CHUNKED_HEAD.to_vec();
+        data.extend(b"5;");
+        data.extend(vec![b'a'; MAX_CHUNK_LINE]);
+        assert_eq!(
+            error_kind(&data, &mut MessageDecoder::new()),
+            Some(ErrorKind::Http(HttpError::InvalidRequest))
+        );
+
+        // trailers count against the head size
+        let mut data = CHUNKED_HEAD.to_vec();
+        data.extend(b"0\r\n");
+        data.extend(b"X-Trailer: value\r\n".repeat(8));
+        let mut decoder = MessageDecoder::with_limits(CHUNKED_HEAD.len() + 64, None);
+        assert_eq!(
+            error_kind(&data, &mut decoder),
+            Some(ErrorKind::Http(HttpError::HeadTooLarge))
+        );
+    }
+
+    #[test]
+    fn pipelined_messages() {
+        let first = [HEAD, b"hello"].concat();
+        let second = b"GET /next HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec();
+        let third = [CHUNKED_HEAD, b"1\r\na\r\n0\r\n\r\n"].concat();
+        let mut buffer = [&first[..], &second[..], &third[..]].concat();
+
+        let mut decoder = MessageDecoder::new();
+        let frames = decode_all(&mut decoder, &mut buffer).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(message(&frames[0..1]), &first[..]);
+        assert_eq!(message(&frames[1..2]), &second[..]);
+        assert_eq!(message(&frames[2..3]), &[CHUNKED_HEAD, b"a"].concat()[..]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn large_bodies_are_streamed() {
+        let length = MAX_BUFFERED_BODY + 10;
+        let head = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", length);
+        let mut decoder = MessageDecoder::new();
+        // the body outgrows the buffer before it is complete
+        let mut buffer = [head.as_bytes(), &vec![b'a'; MAX_BUFFERED_BODY + 1]].concat();
+        let mut frames = decode_all(&mut decoder, &mut buffer).unwrap();
+        buffer.extend(vec![b'a'; 9]);
+        frames.extend(decode_all(&mut decoder, &mut buffer).unwrap());
+
+        match &frames[..] {
+            [Frame::Head(received), Frame::Body(first), Frame::Body(rest), Frame::End] => {
+                assert_eq!(received, head.as_bytes());
+                assert_eq!(first.len() + rest.len(), length);
+            }
+            _ => panic!("expected a streamed body, got {} frames", frames.len()),
+        }
+    }
+}
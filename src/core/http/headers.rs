@@ -0,0 +1,223 @@
+use crate::error::HttpError;
+use crate::Error;
+
+/// Headers of a message in the order they were added.
+///
+/// Names keep their original case but are compared case-insensitively, a name can have
+/// multiple values (ex.: `Set-Cookie`). Names have to be tokens and values can't contain
+/// CR, LF or NUL, so a header can't add further headers to the message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap { entries: vec![] }
+    }
+
+    /// Parses `name: value` lines, lines without a colon or with an invalid name are skipped.
+    pub fn parse(lines: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for line in lines.lines() {
+            // only the first colon separates the name, the value can contain more (ex.: `Host: localhost:80`)
+            if let Some((name, value)) = line.split_once(':') {
+                let _ = headers.append(name.trim(), value.trim());
+            }
+        }
+        headers
+    }
+
+    /// The first value of the header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values of the header in the order they were added.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// All values of the header joined into one comma separated list.
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let values = self.get_all(name);
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.join(", "))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+
+    /// Sets the header, replacing all of its previous values. The header keeps its original position.
+    pub fn insert(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        validate(name, value)?;
+        match self
+            .entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some(position) => {
+                self.entries[position] = (name.to_owned(), value.to_owned());
+                let mut idx = 0;
+                self.entries.retain(|(key, _)| {
+                    idx += 1;
+                    idx - 1 == position || !key.eq_ignore_ascii_case(name)
+                });
+                Ok(())
+            }
+            None => self.append(name, value),
+        }
+    }
+
+    /// Adds a value to the header, keeping the previous values.
+    pub fn append(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        validate(name, value)?;
+        self.entries.push((name.to_owned(), value.to_owned()));
+        Ok(())
+    }
+
+    /// Removes all values of the header.
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn validate(name: &str, value: &str) -> Result<(), Error> {
+    if name.is_empty() || !name.bytes().all(is_token) {
+        return Err(Error::new(
+            &format!("Invalid header name: {:?}.", name),
+            HttpError::InvalidHeader,
+        ));
+    }
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(Error::new(
+            &format!("Invalid value of the {} header.", name),
+            HttpError::InvalidHeader,
+        ));
+    }
+    Ok(())
+}
+
+/// Characters allowed in header and cookie names (RFC 9110 5.6.2).
+pub fn is_token(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn is_invalid(result: Result<(), Error>) -> bool {
+        matches!(
+            result.map_err(|e| e.kind()),
+            Err(ErrorKind::Http(HttpError::InvalidHeader))
+        )
+    }
+
+    #[test]
+    fn case_insensitive_lookup() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/html").unwrap();
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        assert!(headers.contains("Content-type"));
+
+        headers.remove("CONTENT-type");
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn multiple_values() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1").unwrap();
+        headers.append("set-cookie", "b=2").unwrap();
+        assert_eq!(headers.get("Set-Cookie"), Some("a=1"));
+        assert_eq!(headers.get_all("Set-Cookie"), vec!["a=1", "b=2"]);
+        assert_eq!(
+            headers.get_joined("Set-Cookie"),
+            Some(String::from("a=1, b=2"))
+        );
+        assert_eq!(headers.get_joined("Vary"), None);
+
+        // insert replaces all values
+        headers.insert("SET-COOKIE", "c=3").unwrap();
+        assert_eq!(headers.get_all("Set-Cookie"), vec!["c=3"]);
+    }
+
+    #[test]
+    fn ordered_entries() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Date", "now").unwrap();
+        headers.append("Vary", "Accept").unwrap();
+        headers.insert("Content-Length", "0").unwrap();
+        headers.append("Vary", "Accept-Encoding").unwrap();
+        // a replaced header keeps its position
+        headers.insert("date", "later").unwrap();
+
+        let entries: Vec<(&str, &str)> = headers.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("date", "later"),
+                ("Vary", "Accept"),
+                ("Content-Length", "0"),
+                ("Vary", "Accept-Encoding"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_lines() {
+        let headers = HeaderMap::parse(
+            "Host: example.com:8080\r\nAccept:text/html\r\nno colon\r\nBad Name: x\r\nX-Empty:\r\n",
+        );
+        assert_eq!(headers.get("host"), Some("example.com:8080"));
+        assert_eq!(headers.get("accept"), Some("text/html"));
+        assert_eq!(headers.get("x-empty"), Some(""));
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn reject_invalid_names_and_values() {
+        let mut headers = HeaderMap::new();
+        for value in ["a\r\nSet-Cookie: x=y", "a\nb", "a\rb", "a\0b"] {
+            assert!(is_invalid(headers.insert("X-Test", value)), "{:?}", value);
+            assert!(is_invalid(headers.append("X-Test", value)), "{:?}", value);
+        }
+        for name in ["", "X Test", "X-Test:", "X\r\nTest", "Ä"] {
+            assert!(is_invalid(headers.insert(name, "value")), "{:?}", name);
+        }
+        assert!(headers.is_empty());
+        assert!(headers
+            .insert("X-Test", "any \"value\"; with, separators")
+            .is_ok());
+    }
+}
@@ -1,6 +1,14 @@
+use chrono::{DateTime, Utc};
+
+pub mod body;
 pub mod codes;
+pub mod cookies;
+pub mod error_pages;
+pub mod framing;
+pub mod headers;
 pub mod mime;
 pub mod protocol;
+pub mod sse;
 
 pub enum Encoding {
     Gzip,
@@ -16,4 +24,59 @@ impl Range {
     pub fn new(from: Option<usize>, to: Option<usize>) -> Range {
         Range { from, to }
     }
+
+    /// Parses a single byte range from the `Range` header (ex.: `bytes=0-512`, `bytes=512-` or `bytes=-512`).
+    pub fn parse(header: &str) -> Option<Range> {
+        let (unit, range) = header.trim().split_once('=')?;
+        if unit.trim() != "bytes" || range.contains(',') {
+            return None;
+        }
+        let (from, to) = range.trim().split_once('-')?;
+        let parse = |value: &str| -> Option<Option<usize>> {
+            let value = value.trim();
+            if value.is_empty() {
+                return Some(None);
+            }
+            value.parse::<usize>().ok().map(Some)
+        };
+        let range = Range::new(parse(from)?, parse(to)?);
+        match range {
+            Range {
+                from: None,
+                to: None,
+            } => None,
+            Range {
+                from: Some(from),
+                to: Some(to),
+            } if from > to => None,
+            range => Some(range),
+        }
+    }
+}
+
+/// Decodes percent-encoded characters (ex.: `%20`), invalid sequences are kept as they are.
+pub fn decode_uri_component(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = std::str::from_utf8(&bytes[(i + 1)..(i + 3)]).unwrap();
+            decoded.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Formats the date as used by HTTP headers (ex.: `Sun, 06 Nov 1994 08:49:37 GMT`).
+pub fn format_http_date(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
 }
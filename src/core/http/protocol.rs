@@ -1,18 +1,27 @@
+use crate::core::http::body::{header_parameter, FormData, Multipart};
 use crate::core::http::codes::get_phrase_from_code;
-use crate::core::http::Encoding;
+use crate::core::http::cookies::{Cookie, CookieJar};
+use crate::core::http::error_pages::ErrorPages;
+use crate::core::http::format_http_date;
+use crate::core::http::framing::find_head_end;
+use crate::core::http::headers::HeaderMap;
+use crate::core::http::sse::EventStream;
+use crate::core::shutdown::ShutdownSignal;
+use crate::core::tcp::{BodyReceiver, ResponseQueueHandle, Upgrade, WriteHandle};
+use crate::error::{HttpError, SystemError};
+use crate::router::HandlerFuture;
+use crate::session::Session;
 use crate::Error;
-use async_trait::async_trait;
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 use chrono::DateTime;
 use chrono::Duration;
 use chrono::Utc;
-use libflate::{deflate::Encoder as DfEncoder, gzip::Encoder as GzEncoder};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::io::{ReadHalf, WriteHalf};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::sync::Mutex;
 
 use super::codes::HTTPStatus;
@@ -22,6 +31,12 @@ pub enum Method {
     GET,
     HEAD,
     POST,
+    PUT,
+    PATCH,
+    DELETE,
+    OPTIONS,
+    CONNECT,
+    TRACE,
     Unknown,
 }
 impl Method {
@@ -47,10 +62,16 @@ pub struct Request {
     pub method: Method,
     pub path: String,
     pub uri: String,
-    pub body: String,
+    /// The raw body, empty if the body is streamed (see `Request::read_chunk`).
+    pub body: Bytes,
     pub query: String,
     pub http_info: String,
-    pub headers: Headers,
+    pub headers: HeaderMap,
+    /// Path parameters captured by the router (ex.: `id` in `/users/:id`).
+    pub params: HashMap<String, String>,
+    body_stream: Option<Arc<Mutex<BodyReceiver>>>,
+    session: Option<Session>,
+    upgrade: Option<Arc<Mutex<Option<Upgrade>>>>,
 }
 
 impl Request {
@@ -59,7 +80,10 @@ impl Request {
         req_data: Vec<u8>,
         remote_address: SocketAddr,
     ) -> Result<Request, Error> {
-        let req_data = String::from_utf8_lossy(&req_data);
+        // the head is text, the body is kept as raw bytes
+        let head_end = find_head_end(&req_data).unwrap_or(req_data.len());
+        let head = String::from_utf8_lossy(&req_data[..head_end]);
+        let body = Bytes::copy_from_slice(&req_data[head_end..]);
 
         let ParsedRequest {
             method,
@@ -67,9 +91,8 @@ impl Request {
             path,
             query,
             http_info,
-            body,
             headers,
-        } = parse_request(&req_data)?;
+        } = parse_request(&head)?;
         let req = Request {
             // connection,
             remote_address,
@@ -81,10 +104,144 @@ impl Request {
             query: query,
             http_info,
             headers,
+            params: HashMap::new(),
+            body_stream: None,
+            session: None,
+            upgrade: None,
         };
         Ok(req)
     }
 
+    /// Attaches the receiver of a body which was too large to be buffered.
+    pub(crate) fn set_body_stream(&mut self, body: BodyReceiver) {
+        self.body_stream = Some(Arc::new(Mutex::new(body)));
+    }
+
+    /// The id of the last Server-Sent Event the client received, sent when it reconnects.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.headers.get("last-event-id")
+    }
+
+    pub(crate) fn set_upgrade(&mut self, upgrade: Upgrade) {
+        self.upgrade = Some(Arc::new(Mutex::new(Some(upgrade))));
+    }
+
+    /// Whether the request asks to switch to another protocol (ex.: WebSocket).
+    pub fn is_upgrade(&self) -> bool {
+        self.upgrade.is_some()
+    }
+
+    /// Takes the upgrade out of the request, it can only be accepted once.
+    pub(crate) async fn take_upgrade(&self) -> Option<Upgrade> {
+        self.upgrade.as_ref()?.lock().await.take()
+    }
+
+    pub(crate) fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    /// The session of the request, None unless the `Sessions` middleware is used.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Whether the body has to be read with `read_chunk` or `read_body`.
+    pub fn is_body_streamed(&self) -> bool {
+        self.body_stream.is_some()
+    }
+
+    /// The body as UTF-8 text.
+    pub fn text(&self) -> Result<&str, Error> {
+        std::str::from_utf8(&self.body).map_err(|e| {
+            Error::new("Body is not valid UTF-8.", HttpError::InvalidRequest).with_source(e)
+        })
+    }
+
+    /// The body as text, invalid UTF-8 sequences are replaced.
+    pub fn text_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+
+    /// Reads the next part of the body, returns None once the whole body was read.
+    /// A buffered body is returned in one piece and taken out of `body`.
+    pub async fn read_chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        if !self.body.is_empty() {
+            return Ok(Some(std::mem::take(&mut self.body)));
+        }
+        match &self.body_stream {
+            Some(body_stream) => body_stream.lock().await.recv().await.transpose(),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the rest of a streamed body into `body` and returns the whole body.
+    pub async fn read_body(&mut self) -> Result<Bytes, Error> {
+        if let Some(body_stream) = self.body_stream.take() {
+            let mut body = BytesMut::from(&self.body[..]);
+            let mut body_stream = body_stream.lock().await;
+            while let Some(chunk) = body_stream.recv().await {
+                body.extend_from_slice(&chunk?);
+            }
+            self.body = body.freeze();
+        }
+        Ok(self.body.clone())
+    }
+
+    /// The query string decoded into key-value pairs.
+    pub fn query_params(&self) -> FormData {
+        FormData::parse(&self.query)
+    }
+
+    /// Reads the whole body and decodes it as `application/x-www-form-urlencoded` data.
+    pub async fn form(&mut self) -> Result<FormData, Error> {
+        let body = self.read_body().await?;
+        Ok(FormData::parse(&String::from_utf8_lossy(&body)))
+    }
+
+    /// Reads the whole body and deserializes it from JSON.
+    pub async fn json<T: DeserializeOwned>(&mut self) -> Result<T, Error> {
+        let body = self.read_body().await?;
+        serde_json::from_slice(&body).map_err(|e| {
+            Error::new(
+                &format!("Invalid JSON body: {}", e),
+                HttpError::InvalidRequest,
+            )
+            .with_source(e)
+        })
+    }
+
+    /// Reads a `multipart/form-data` body part by part, fails if the request has a different content type.
+    pub fn multipart(&self) -> Result<Multipart, Error> {
+        let content_type = self.headers.get("content-type").ok_or(Error::new(
+            "Missing Content-Type header.",
+            HttpError::InvalidRequest,
+        ))?;
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+            return Err(Error::new(
+                "Body is not multipart/form-data.",
+                HttpError::InvalidRequest,
+            ));
+        }
+        let boundary = header_parameter(content_type, "boundary")
+            .filter(|boundary| !boundary.is_empty())
+            .ok_or(Error::new(
+                "Missing multipart boundary.",
+                HttpError::InvalidRequest,
+            ))?;
+        Ok(Multipart::new(self.clone(), &boundary))
+    }
+
+    /// Cookies sent with the request.
+    pub fn cookies(&self) -> CookieJar {
+        CookieJar::parse(&self.headers.get_all("cookie").join("; "))
+    }
+
+    /// Returns a path parameter captured by the router.
+    pub fn param(&self, name: &str) -> Option<&String> {
+        self.params.get(name)
+    }
+
     /// DateTime of when the connection was established
     pub fn time_received(&self) -> DateTime<Utc> {
         self.time_received
@@ -96,54 +253,133 @@ impl Request {
     }
 }
 
-type Headers = HashMap<String, String>;
-#[derive(Debug)]
+// size of the chunks read by `Response::send_reader`
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Transforms the response body right before it is written, registered with `Response::on_send`.
+pub type SendHook = Box<dyn Fn(&mut Response, Vec<u8>) -> Vec<u8> + Send + Sync>;
+type BeforeSendHook = Box<dyn FnOnce() -> HandlerFuture + Send + Sync>;
+
 pub struct Response {
-    write_handle: Arc<Mutex<WriteHalf<TcpStream>>>,
+    response_queue: ResponseQueueHandle,
+    // position of the request on its connection, responses are written in this order
+    index: u32,
     req: Request,
     sent: bool,
-    headers: Headers,
+    finished: bool,
+    chunked: bool,
+    headers: HeaderMap,
+    trailers: HeaderMap,
     status: HTTPStatus,
-    use_compression: bool,
     is_last: bool,
+    // in-flight responses close the connection once the server shuts down
+    shutdown: ShutdownSignal,
+    error_pages: Arc<ErrorPages>,
+    send_hooks: Vec<SendHook>,
+    before_send_hooks: Vec<BeforeSendHook>,
+}
+
+impl std::fmt::Debug for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Response")
+            .field("index", &self.index)
+            .field("req", &self.req)
+            .field("sent", &self.sent)
+            .field("finished", &self.finished)
+            .field("chunked", &self.chunked)
+            .field("headers", &self.headers)
+            .field("status", &self.status)
+            .field("is_last", &self.is_last)
+            .finish()
+    }
 }
 
 impl Response {
     pub fn __new(
-        write_handle: Arc<Mutex<WriteHalf<TcpStream>>>,
+        response_queue: ResponseQueueHandle,
+        index: u32,
         req: Request,
-        use_compression: bool,
         is_last: bool,
+        shutdown: ShutdownSignal,
+        error_pages: Arc<ErrorPages>,
     ) -> Response {
         Response {
-            write_handle,
+            response_queue,
+            index,
             req,
             sent: false,
-            headers: HashMap::new(),
+            finished: false,
+            chunked: false,
+            headers: HeaderMap::new(),
+            trailers: HeaderMap::new(),
             status: HTTPStatus::Accepted,
-            use_compression,
             is_last,
+            shutdown,
+            error_pages,
+            send_hooks: vec![],
+            before_send_hooks: vec![],
         }
     }
+
+    /// The request this response is answering.
+    pub fn request(&self) -> &Request {
+        &self.req
+    }
+
+    /// Registers a hook which can modify the headers and the body before the response is sent,
+    /// hooks run in the order they were registered.
+    pub fn on_send<F>(&mut self, hook: F)
+    where
+        F: Fn(&mut Response, Vec<u8>) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.send_hooks.push(Box::new(hook));
+    }
+
+    /// Awaits the hook before the head is written (ex.: to store the session the cookie refers to).
+    pub(crate) fn before_send<F>(&mut self, hook: F)
+    where
+        F: FnOnce() -> HandlerFuture + Send + Sync + 'static,
+    {
+        self.before_send_hooks.push(Box::new(hook));
+    }
+
+    async fn run_before_send_hooks(&mut self) {
+        for hook in std::mem::take(&mut self.before_send_hooks) {
+            hook().await;
+        }
+    }
+
     pub fn get_headers(&mut self) -> String {
         let mut headers = String::new();
-        for (key, value) in &self.headers {
+        for (key, value) in self.headers.iter() {
             let mut header = String::new();
-            header.push_str(&key);
+            header.push_str(key);
             if value.len() > 0 {
                 header.push_str(": ");
             }
-            header.push_str(&value);
+            header.push_str(value);
             header.push_str("\n");
             headers.push_str(&header);
         }
         headers
     }
-    pub fn get_header(&self, key: &str) -> Option<&String> {
+    pub fn get_header(&self, key: &str) -> Option<&str> {
         self.headers.get(key)
     }
-    pub fn set_header(&mut self, key: &str, value: &str) {
-        self.headers.insert(key.to_owned(), value.to_owned());
+    /// Sets the header, replacing its previous values.
+    /// Fails if the name isn't a token or the value contains CR, LF or NUL.
+    pub fn set_header(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        self.headers.insert(key, value)
+    }
+    /// Adds another value to the header (ex.: multiple `Set-Cookie` headers).
+    pub fn append_header(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        self.headers.append(key, value)
+    }
+    /// Adds a `Set-Cookie` header, can be called multiple times.
+    /// Fails if a part of the cookie would change the header (see `Cookie::validate`).
+    pub fn set_cookie(&mut self, cookie: &Cookie) -> Result<(), Error> {
+        cookie.validate()?;
+        self.headers.append("Set-Cookie", &cookie.to_string())
     }
     pub fn remove_header(&mut self, key: &str) {
         self.headers.remove(key);
@@ -156,90 +392,298 @@ impl Response {
         self.status = status;
     }
 
-    pub async fn send(&mut self, data: &[u8]) -> Result<(), String> {
-        if self.sent {
-            return Err("Cannot write to a response that has already been sent.".into());
-        }
-        self.sent = true;
+    /// Whether the body is sent in chunks (see `Response::write_chunk`).
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
 
-        // create all headers
-        let status = self.get_status();
-        let code =
-            get_phrase_from_code(&status).ok_or(format!("Invalid status code: {:?}.", status))?;
-        let http_header = format!("HTTP/1.1 {} {}\n", code.0, code.1);
+    /// Adds a trailer which is sent after the last chunk of a chunked response.
+    /// Trailer names should be announced in the `Trailer` header before the first chunk is written.
+    pub fn set_trailer(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        self.trailers.insert(key, value)
+    }
 
-        let date = Utc::now().format("%a, %d %b %Y %T %Z");
-        let date = format!("{}", date);
-        self.set_header("Date", &date);
+    // runs the send hooks and creates the status line with all headers,
+    // the Content-Length is the given length or the length of the body
+    fn create_head(
+        &mut self,
+        body: Vec<u8>,
+        length: Option<usize>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        self.set_header("Date", &format_http_date(&Utc::now()))?;
 
         let mut connection_status = "keep-alive";
-        if self.is_last {
+        if self.is_last || self.shutdown.is_closing() {
             connection_status = "close";
         }
-        self.set_header("Connection", connection_status);
-
-        let mut body = data.to_vec();
-
-        // use compression if necessary
-        if self.use_compression {
-            let accept_encoding = self.req.headers.get("accept-encoding");
-            if let Some(accept_encoding) = accept_encoding {
-                let accept_encoding: Vec<&str> = accept_encoding.split(",").collect();
-                let accept_encoding: Vec<String> = accept_encoding
-                    .iter()
-                    .map(|&i| i.trim().to_lowercase())
-                    .collect();
-                let has_gzip = accept_encoding.contains(&String::from("gzip"));
-                let has_deflate = accept_encoding.contains(&String::from("deflate"));
-
-                let content = &data;
-                let mut content_encoding: Option<Encoding> = None;
-
-                // Gzip usually increases file size in files with less than 1000 bytes
-                if content.len() > 1000 && has_gzip {
-                    content_encoding = Some(Encoding::Gzip);
-                } else if has_deflate {
-                    content_encoding = Some(Encoding::Deflate);
-                };
+        self.set_header("Connection", connection_status)?;
 
-                if let Some(content_encoding) = content_encoding {
-                    let (encoded_data, encoding_name) = match content_encoding {
-                        Encoding::Gzip => {
-                            let mut encoder = GzEncoder::new(Vec::new()).unwrap();
-                            std::io::copy(&mut &content[..], &mut encoder).unwrap();
-                            (encoder.finish().into_result().unwrap(), "gzip")
-                        }
-                        Encoding::Deflate => {
-                            let mut encoder = DfEncoder::new(Vec::new());
-                            std::io::copy(&mut &content[..], &mut encoder).unwrap();
-                            (encoder.finish().into_result().unwrap(), "deflate")
-                        }
-                    };
-
-                    self.set_header("Content-Encoding", encoding_name);
-                    body = encoded_data;
-                }
-            }
+        let mut body = body;
+        let send_hooks = std::mem::take(&mut self.send_hooks);
+        for hook in &send_hooks {
+            body = hook(self, body);
         }
 
-        self.set_header("Content-Length", &body.len().to_string());
+        // create the status line, hooks may have changed the status
+        let status = self.get_status();
+        let code = get_phrase_from_code(&status).ok_or(Error::new(
+            &format!("Invalid status code: {:?}.", status),
+            HttpError::InvalidStatus,
+        ))?;
+        let http_header = format!("HTTP/1.1 {} {}\n", code.0, code.1);
+
+        // informational, 204 and 304 responses never have a body
+        let code = status.code();
+        let body = if code < 200 || code == 204 || code == 304 {
+            self.remove_header("Content-Length");
+            vec![]
+        } else if self.chunked {
+            self.remove_header("Content-Length");
+            self.set_header("Transfer-Encoding", "chunked")?;
+            body
+        } else {
+            let length = length.unwrap_or(body.len());
+            self.set_header("Content-Length", &length.to_string())?;
+            body
+        };
 
         let mut headers = [http_header.as_bytes(), self.get_headers().as_bytes()].concat();
 
         headers.push(0x0A);
+        Ok((headers, body))
+    }
+
+    async fn write(&mut self, data: &[u8], finished: bool) -> Result<(), Error> {
+        self.finished = finished;
+        let mut response_queue = self.response_queue.lock().await;
+
+        response_queue
+            .write(self.index, data, finished)
+            .await
+            .map_err(|e| {
+                Error::new("Failed to write the response.", SystemError::StreamWrite).with_source(e)
+            })?;
+        // the connection ends with the response to the last request (a rejected upgrade included)
+        if finished && self.is_last {
+            let _ = response_queue.close_after(self.index + 1).await;
+        }
+        Ok(())
+    }
+
+    pub async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.sent {
+            return Err(already_sent());
+        }
+        self.sent = true;
+
+        self.run_before_send_hooks().await;
+        let (headers, body) = self.create_head(data.to_vec(), None)?;
 
         let final_data = match self.req.method {
             Method::HEAD => headers,
             _ => [&headers, &body[..]].concat(),
         };
 
-        let mut connection = self.write_handle.lock().await;
+        self.write(&final_data, true).await
+    }
+
+    /// Sends the error page configured for the status (see `CreeServer::error_page`).
+    pub async fn send_error(&mut self, status: HTTPStatus) -> Result<(), Error> {
+        let (content_type, body) = self.error_pages.get(&status);
+        self.set_status(status);
+        self.set_header("Content-Type", &content_type)?;
+        self.send(&body).await
+    }
+
+    /// Sends a part of the body using chunked transfer encoding, the headers are sent with the first chunk.
+    /// The response has to be finished with `Response::end`.
+    pub async fn write_chunk(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.finished || (self.sent && !self.chunked) {
+            return Err(already_sent());
+        }
+        if !self.sent {
+            self.sent = true;
+            self.chunked = true;
+
+            // send hooks only see the headers of a chunked response
+            self.run_before_send_hooks().await;
+            let (headers, _) = self.create_head(vec![], None)?;
+            self.write(&headers, false).await?;
+        }
+
+        // an empty chunk would end the body
+        if data.is_empty() || self.req.method == Method::HEAD {
+            return Ok(());
+        }
+        let chunk = [format!("{:X}\r\n", data.len()).as_bytes(), data, b"\r\n"].concat();
+        self.write(&chunk, false).await
+    }
+
+    /// Ends a chunked response, sending the last chunk and all trailers.
+    pub async fn end(&mut self) -> Result<(), Error> {
+        if self.finished || (self.sent && !self.chunked) {
+            return Err(already_sent());
+        }
+        self.write_chunk(&[]).await?;
+
+        let mut last_chunk = vec![];
+        if self.req.method != Method::HEAD {
+            last_chunk.extend(b"0\r\n");
+            for (key, value) in self.trailers.iter() {
+                last_chunk.extend(format!("{}: {}\r\n", key, value).as_bytes());
+            }
+            last_chunk.extend(b"\r\n");
+        }
+        self.write(&last_chunk, true).await
+    }
+
+    /// Turns the response into a stream of Server-Sent Events, the headers are sent right away.
+    pub async fn event_stream(self) -> Result<EventStream, Error> {
+        EventStream::new(self).await
+    }
+
+    /// Sends `101 Switching Protocols` with the headers of the response and returns the handle
+    /// used to write the new protocol. Send hooks don't run for this response.
+    pub(crate) async fn switch_protocols(&mut self) -> Result<WriteHandle, Error> {
+        if self.sent {
+            return Err(already_sent());
+        }
+        let mut response_queue = self.response_queue.lock().await;
+        // data of the new protocol must not overtake responses to earlier pipelined requests
+        if !response_queue.is_next(self.index) {
+            return Err(Error::new(
+                "Responses to earlier requests were not sent yet.",
+                HttpError::ResponsesPending,
+            ));
+        }
+
+        let mut head = String::from("HTTP/1.1 101 Switching Protocols\r\n");
+        for (key, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        head.push_str("\r\n");
+
+        self.sent = true;
+        self.finished = true;
+        response_queue
+            .write(self.index, head.as_bytes(), true)
+            .await
+            .map_err(|e| {
+                Error::new("Failed to write the response.", SystemError::StreamWrite).with_source(e)
+            })?;
+        Ok(response_queue.get_write_handle().clone())
+    }
+
+    /// Sends everything from the reader as a chunked response.
+    pub async fn send_reader<R>(&mut self, mut reader: R) -> Result<(), Error>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        loop {
+            let length = reader.read(&mut buffer).await.map_err(|e| {
+                Error::new("Failed to read the response body.", SystemError::StreamRead)
+                    .with_source(e)
+            })?;
+            if length == 0 {
+                break;
+            }
+            self.write_chunk(&buffer[..length]).await?;
+        }
+        self.end().await
+    }
 
-        connection.write_all(&final_data).await.unwrap();
+    /// Sends `length` bytes from the reader with a `Content-Length` header instead of chunked
+    /// transfer encoding. The reader isn't read for HEAD requests.
+    pub async fn send_reader_sized<R>(&mut self, mut reader: R, length: usize) -> Result<(), Error>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        if self.sent {
+            return Err(already_sent());
+        }
+        self.sent = true;
+
+        // send hooks only see the headers
+        self.run_before_send_hooks().await;
+        let (headers, _) = self.create_head(vec![], Some(length))?;
+        if length == 0 || self.req.method == Method::HEAD {
+            return self.write(&headers, true).await;
+        }
+        self.write(&headers, false).await?;
+
+        let mut buffer = vec![0u8; CHUNK_SIZE.min(length)];
+        let mut remaining = length;
+        while remaining > 0 {
+            let size = buffer.len().min(remaining);
+            let read = reader.read(&mut buffer[..size]).await.map_err(|e| {
+                Error::new("Failed to read the response body.", SystemError::StreamRead)
+                    .with_source(e)
+            })?;
+            // the response can't be finished, it is cut off when dropped
+            if read == 0 {
+                return Err(Error::new(
+                    "The response body is shorter than its length.",
+                    SystemError::StreamRead,
+                ));
+            }
+            remaining -= read;
+            self.write(&buffer[..read], remaining == 0).await?;
+        }
         Ok(())
     }
 }
 
+fn already_sent() -> Error {
+    Error::new(
+        "Cannot write to a response that has already been sent.",
+        HttpError::ResponseSent,
+    )
+}
+
+impl Drop for Response {
+    fn drop(&mut self) {
+        // a response which was never finished would block all following responses on the connection
+        if !self.finished {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let response_queue = self.response_queue.clone();
+                let index = self.index;
+                // the handler panicked or returned without responding, a started response can only be cut off
+                let error_response = if self.sent {
+                    None
+                } else {
+                    // panics are already reported by the runtime
+                    if !std::thread::panicking() {
+                        eprintln!(
+                            "No response was sent to {}, answering with 500.",
+                            self.req.path
+                        );
+                    }
+                    let close = self.is_last || self.shutdown.is_closing();
+                    let with_body = self.req.method != Method::HEAD;
+                    Some(
+                        self.error_pages
+                            .response(&HTTPStatus::ServerError, with_body, close),
+                    )
+                };
+                let is_last = self.is_last;
+                runtime.spawn(async move {
+                    let mut response_queue = response_queue.lock().await;
+                    let _ = match error_response {
+                        Some(error_response) => {
+                            response_queue.write(index, &error_response, true).await
+                        }
+                        None => response_queue.abandon(index).await,
+                    };
+                    if is_last {
+                        let _ = response_queue.close_after(index + 1).await;
+                    }
+                });
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ParsedRequest {
     method: Method,
@@ -247,51 +691,40 @@ struct ParsedRequest {
     path: String,
     http_info: String,
     query: String,
-    body: String,
-    headers: Headers,
+    headers: HeaderMap,
 }
 
-// takes the raw UTF-8 request and extracts message data from it
-fn parse_request(req: &str) -> Result<ParsedRequest, Error> {
-    let req = req.trim();
+// takes the request line and headers and extracts message data from them
+fn parse_request(head: &str) -> Result<ParsedRequest, Error> {
+    let head = head.trim();
 
-    let req = req.replace("\r", "");
+    let head = head.replace("\r", "");
 
-    // split body and header section
-    let parts: Vec<&str> = req.split("\n\n").collect();
-    if parts.len() == 0 {
-        return Err(Error::new("Invalid request", 2001));
-    }
-    let head: Vec<&str> = parts[0].lines().collect();
-    if head.len() < 1 {
-        return Err(Error::new("Invalid request", 2001));
+    let head: Vec<&str> = head.lines().collect();
+    if head.is_empty() {
+        return Err(Error::new("Invalid request", HttpError::InvalidRequest));
     }
     let request_line: Vec<&str> = head[0].split_whitespace().collect();
 
     // extract headers
-    let raw_headers = &head[1..];
-    let mut headers: Headers = HashMap::new();
-    if raw_headers.len() > 0 {
-        for header in raw_headers {
-            let parts: Vec<&str> = header.split(":").collect();
-            if parts.len() != 2 {
-                continue;
-            }
-
-            headers.insert(parts[0].trim().to_lowercase(), parts[1].trim().to_owned());
-        }
-    }
+    let headers = HeaderMap::parse(&head[1..].join("\n"));
 
     if request_line.len() < 3 {
-        return Err(Error::new("Invalid request.", 2001));
+        return Err(Error::new("Invalid request.", HttpError::InvalidRequest));
     }
     let method: Method = match request_line[0] {
         "GET" => Method::GET,
         "HEAD" => Method::HEAD,
         "POST" => Method::POST,
+        "PUT" => Method::PUT,
+        "PATCH" => Method::PATCH,
+        "DELETE" => Method::DELETE,
+        "OPTIONS" => Method::OPTIONS,
+        "CONNECT" => Method::CONNECT,
+        "TRACE" => Method::TRACE,
         _ => {
             Method::Unknown
-            // return Err(Error::new("Invalid request method.", 2002));
+            // return Err(Error::new("Invalid request method.", HttpError::InvalidMethod));
         }
     };
 
@@ -315,11 +748,6 @@ fn parse_request(req: &str) -> Result<ParsedRequest, Error> {
         path,
         http_info: String::from(request_line[2]),
         query: query.to_owned(),
-        body: if parts.len() == 2 {
-            parts[1].to_owned()
-        } else {
-            String::from("")
-        },
         headers,
     };
     Ok(parsed)
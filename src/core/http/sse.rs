@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use super::codes::HTTPStatus;
+use super::protocol::Response;
+use crate::Error;
+
+/// A single Server-Sent Event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    /// An event with the given data, multi-line data is sent as multiple `data` fields.
+    pub fn new(data: &str) -> Event {
+        Event {
+            data: data.to_owned(),
+            ..Event::default()
+        }
+    }
+
+    /// Name of the event, clients receive unnamed events as `message`.
+    pub fn event(mut self, event: &str) -> Event {
+        self.event = Some(event.to_owned());
+        self
+    }
+
+    /// Id sent back by the client in the `Last-Event-ID` header when it reconnects.
+    pub fn id(mut self, id: &str) -> Event {
+        self.id = Some(id.to_owned());
+        self
+    }
+
+    /// Time the client waits before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Serializes the event in the `text/event-stream` format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut event = String::new();
+        // line breaks would end the field early
+        if let Some(name) = &self.event {
+            event.push_str(&format!("event: {}\n", single_line(name)));
+        }
+        if let Some(id) = &self.id {
+            // an id containing NULL is ignored by the client
+            event.push_str(&format!("id: {}\n", single_line(id).replace('\0', "")));
+        }
+        if let Some(retry) = &self.retry {
+            event.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split('\n') {
+            event.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        event.push('\n');
+        event.into_bytes()
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// A response which stays open and pushes events to the client, created with `Response::event_stream`.
+pub struct EventStream {
+    res: Response,
+}
+
+impl EventStream {
+    pub(crate) async fn new(mut res: Response) -> Result<EventStream, Error> {
+        // EventSource rejects any other status
+        res.set_status(HTTPStatus::Ok);
+        res.set_header("Content-Type", "text/event-stream")?;
+        res.set_header("Cache-Control", "no-cache")?;
+        // the headers are sent right away so the client knows the stream is open
+        res.write_chunk(&[]).await?;
+        Ok(EventStream { res })
+    }
+
+    /// The id of the last event the client received before reconnecting.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.res.request().last_event_id()
+    }
+
+    /// Sends the event, fails once the client disconnected.
+    pub async fn send(&mut self, event: &Event) -> Result<(), Error> {
+        self.res.write_chunk(&event.to_bytes()).await
+    }
+
+    /// Sends only data, as an unnamed event.
+    pub async fn send_data(&mut self, data: &str) -> Result<(), Error> {
+        self.send(&Event::new(data)).await
+    }
+
+    /// Sends a comment, which clients ignore. Useful to keep idle connections open.
+    pub async fn comment(&mut self, comment: &str) -> Result<(), Error> {
+        let comment = format!(": {}\n\n", single_line(comment));
+        self.res.write_chunk(comment.as_bytes()).await
+    }
+
+    /// Ends the stream, clients reconnect after the retry time unless they are told otherwise.
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.res.end().await
+    }
+}
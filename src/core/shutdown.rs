@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::time;
+
+use crate::error::TcpError;
+use crate::Error;
+
+struct ShutdownState {
+    closing: watch::Sender<bool>,
+    finished: watch::Sender<bool>,
+    // every signal holds a clone of the guard, the server is drained once all of them were dropped
+    guard: StdMutex<Option<mpsc::Sender<()>>>,
+    drained: Mutex<mpsc::Receiver<()>>,
+}
+
+/// Stops a running server, obtained with `CreeServer::shutdown_handle`.
+///
+/// The listener stops accepting connections, responses which are still in progress are sent
+/// with `Connection: close` and every connection is closed once its responses were written.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> ShutdownHandle {
+        let (guard, drained) = mpsc::channel(1);
+        ShutdownHandle {
+            state: Arc::new(ShutdownState {
+                closing: watch::channel(false).0,
+                finished: watch::channel(false).0,
+                guard: StdMutex::new(Some(guard)),
+                drained: Mutex::new(drained),
+            }),
+        }
+    }
+
+    /// A signal for a listener, connection or response, None once the shutdown started.
+    pub(crate) fn signal(&self) -> Option<ShutdownSignal> {
+        let guard = self.state.guard.lock().unwrap_or_else(|e| e.into_inner());
+        Some(ShutdownSignal {
+            closing: self.state.closing.subscribe(),
+            _guard: guard.as_ref()?.clone(),
+        })
+    }
+
+    pub fn is_closing(&self) -> bool {
+        *self.state.closing.borrow()
+    }
+
+    /// Stops the server and resolves once all connections were drained.
+    /// Long-lived responses (ex.: event streams) have to end on their own, see `ShutdownHandle::shutdown_with_timeout`.
+    pub async fn shutdown(&self) {
+        self.state.closing.send_replace(true);
+        self.state
+            .guard
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+
+        // the receiver returns None after the last guard was dropped
+        self.state.drained.lock().await.recv().await;
+        self.state.finished.send_replace(true);
+    }
+
+    /// Like `ShutdownHandle::shutdown`, but gives up waiting for the remaining connections after the timeout.
+    pub async fn shutdown_with_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        let result = time::timeout(timeout, self.shutdown()).await;
+        self.state.finished.send_replace(true);
+        result.or(Err(Error::new(
+            "Connections were still open when the shutdown timed out.",
+            TcpError::ShutdownTimeout,
+        )))
+    }
+
+    /// Resolves once a started shutdown completed or timed out.
+    pub(crate) async fn finished(&self) {
+        let mut finished = self.state.finished.subscribe();
+        while !*finished.borrow_and_update() {
+            if finished.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Shuts down with the given timeout once the process receives SIGINT (Ctrl+C) or SIGTERM.
+    pub(crate) fn on_signal(&self, timeout: Duration) {
+        let handle = self.clone();
+        tokio::spawn(async move {
+            termination_signal().await;
+            println!("Shutting down...");
+            if let Err(e) = handle.shutdown_with_timeout(timeout).await {
+                eprintln!("{}", e);
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn termination_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = sigterm.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn termination_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Held by everything the server waits for during a shutdown.
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    closing: watch::Receiver<bool>,
+    _guard: mpsc::Sender<()>,
+}
+
+impl ShutdownSignal {
+    pub fn is_closing(&self) -> bool {
+        *self.closing.borrow()
+    }
+
+    /// Resolves once the shutdown started.
+    pub async fn closing(&mut self) {
+        while !*self.closing.borrow_and_update() {
+            if self.closing.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ShutdownSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShutdownSignal")
+            .field("closing", &self.is_closing())
+            .finish()
+    }
+}
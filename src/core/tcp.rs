@@ -1,150 +1,503 @@
+use crate::core::http::framing::{is_upgrade_request, Frame, MessageDecoder, MAX_HEAD_SIZE};
+use crate::error::{HttpError, SystemError, TcpError};
 use crate::Error;
+use async_trait::async_trait;
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use tokio::sync::Mutex;
 
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
-use tokio::io::WriteHalf;
+use tokio::io::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{self, Receiver};
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 use tokio::time;
 
 pub const TCP_MAX_MESSAGES: u32 = 1024;
 const CONNECTION_STALLING_LIMIT: Duration = Duration::from_secs(60);
-const BUFFER_SIZE: usize = 128;
+const HEAD_TIMEOUT: Duration = Duration::from_secs(30);
+const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+const BUFFER_SIZE: usize = 8192;
+const BODY_CHANNEL_SIZE: usize = 16;
+
+/// Limits applied to every connection, configured with `api::CreeOptions`.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    /// Time a connection may stay idle before the next request, it is closed afterwards.
+    pub keep_alive_timeout: Duration,
+    /// Number of requests after which the connection is closed.
+    pub max_requests: u32,
+    /// Time the client has to send the whole head once a request was started.
+    pub header_timeout: Duration,
+    /// Time the client may stay silent while sending a body.
+    pub body_timeout: Duration,
+    pub max_header_size: usize,
+    /// None accepts bodies of any size.
+    pub max_body_size: Option<usize>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits {
+            keep_alive_timeout: CONNECTION_STALLING_LIMIT,
+            max_requests: TCP_MAX_MESSAGES,
+            header_timeout: HEAD_TIMEOUT,
+            body_timeout: BODY_TIMEOUT,
+            max_header_size: MAX_HEAD_SIZE,
+            max_body_size: None,
+        }
+    }
+}
+
+/// Shared handle used by responses to write to the underlying connection.
+pub type WriteHandle = Arc<Mutex<Box<dyn ConnectionWriter>>>;
+
+/// The reading side of a connection, either a plain TCP stream or a decrypted TLS stream.
+#[async_trait]
+pub trait ConnectionReader: Send {
+    /// Reads available data into the buffer, returns 0 once the connection was closed.
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error>;
+}
+
+/// The writing side of a connection, either a plain TCP stream or an encrypted TLS stream.
+#[async_trait]
+pub trait ConnectionWriter: Send + std::fmt::Debug {
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error>;
+    async fn shutdown(&mut self) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl ConnectionReader for ReadHalf<TcpStream> {
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        AsyncReadExt::read(self, buffer).await.map_err(|e| {
+            Error::new("Failed to read from the stream.", SystemError::StreamRead).with_source(e)
+        })
+    }
+}
+
+#[async_trait]
+impl ConnectionWriter for WriteHalf<TcpStream> {
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write_all(data).await.map_err(|e| {
+            Error::new("Failed to write to the stream.", SystemError::StreamWrite).with_source(e)
+        })?;
+
+        if let Err(e) = self.flush().await {
+            return Err(
+                Error::new("Failed to flush the stream.", SystemError::StreamFlush).with_source(e),
+            );
+        };
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        if let Err(e) = AsyncWriteExt::shutdown(self).await {
+            return Err(Error::new(
+                "Failed to close the connection.",
+                SystemError::StreamShutdown,
+            )
+            .with_source(e));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct PendingResponse {
+    data: Vec<u8>,
+    finished: bool,
+    close: bool,
+}
+
+/// Writes the responses of one connection in the same order their requests were received.
+/// Data of a response is written right away if all previous responses were finished,
+/// otherwise it is buffered until it is its turn.
+pub struct ResponseQueue {
+    write_handle: WriteHandle,
+    next_index: u32,
+    pending: BTreeMap<u32, PendingResponse>,
+    // the connection is closed once all responses before this index were written
+    close_at: Option<u32>,
+}
+
+pub type ResponseQueueHandle = Arc<Mutex<ResponseQueue>>;
+
+impl ResponseQueue {
+    pub fn new(write_handle: WriteHandle) -> ResponseQueue {
+        ResponseQueue {
+            write_handle,
+            next_index: 0,
+            pending: BTreeMap::new(),
+            close_at: None,
+        }
+    }
+
+    /// Whether all responses before the given index were written.
+    pub fn is_next(&self, index: u32) -> bool {
+        self.next_index == index
+    }
+
+    pub fn get_write_handle(&self) -> &WriteHandle {
+        &self.write_handle
+    }
+
+    /// Queues data of the response at the given index, `finished` marks the end of the response.
+    pub async fn write(&mut self, index: u32, data: &[u8], finished: bool) -> Result<(), Error> {
+        if index < self.next_index {
+            return Err(Error::new(
+                "The response was already finished.",
+                SystemError::StreamWrite,
+            ));
+        }
+        let pending = self.pending.entry(index).or_default();
+        pending.data.extend_from_slice(data);
+        pending.finished |= finished;
+        self.flush().await
+    }
+
+    /// Gives up the response at the given index, since the client would wait for it
+    /// forever the connection is closed once all previous responses were written.
+    pub async fn abandon(&mut self, index: u32) -> Result<(), Error> {
+        if index < self.next_index {
+            return Ok(());
+        }
+        self.pending.entry(index).or_default().close = true;
+        self.flush().await
+    }
+
+    /// Closes the connection once the responses to the first `count` requests were written.
+    pub async fn close_after(&mut self, count: u32) -> Result<(), Error> {
+        self.close_at = Some(self.close_at.map_or(count, |close_at| close_at.max(count)));
+        self.flush().await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        loop {
+            if self
+                .close_at
+                .is_some_and(|close_at| self.next_index >= close_at)
+            {
+                self.close_at = None;
+                self.pending.clear();
+                return self.write_handle.lock().await.shutdown().await;
+            }
+            let pending = match self.pending.get_mut(&self.next_index) {
+                Some(pending) => pending,
+                None => break,
+            };
+            if !pending.data.is_empty() {
+                let data = std::mem::take(&mut pending.data);
+                self.write_handle.lock().await.write(&data).await?;
+            }
+            if pending.close {
+                self.pending.clear();
+                return self.write_handle.lock().await.shutdown().await;
+            }
+            if !pending.finished {
+                break;
+            }
+            self.pending.remove(&self.next_index);
+            self.next_index += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Chunks of a streamed message body, the channel closes once the whole body was read.
+pub type BodyReceiver = Receiver<Result<Bytes, Error>>;
 
 pub struct TcpMessage {
     pub time_received: DateTime<Utc>,
     pub content: Vec<u8>,
+    /// Set if the body was too large to be buffered, `content` then only contains the head.
+    pub body: Option<BodyReceiver>,
+    /// Set if the message asks to switch protocols (`Connection: upgrade`).
+    pub upgrade: Option<Upgrade>,
+}
+
+impl TcpMessage {
+    fn new(content: Vec<u8>, body: Option<BodyReceiver>, upgrade: Option<Upgrade>) -> TcpMessage {
+        TcpMessage {
+            time_received: Utc::now(),
+            content,
+            body,
+            upgrade,
+        }
+    }
+}
+
+/// The reading side of a connection taken over by another protocol, together with
+/// the data which was already received after the upgrade request.
+pub struct RawReader {
+    pub reader: Box<dyn ConnectionReader>,
+    pub buffered: Vec<u8>,
+}
+
+/// Reading stops after an upgrade request until it is decided whether the protocol is switched.
+/// Dropping the upgrade rejects it and the connection continues with HTTP.
+pub struct Upgrade {
+    decision: oneshot::Sender<oneshot::Sender<RawReader>>,
+}
+
+impl std::fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Upgrade").finish()
+    }
+}
+
+impl Upgrade {
+    /// Takes over the reading side of the connection, no more HTTP messages are read afterwards.
+    pub async fn accept(self) -> Result<RawReader, Error> {
+        let (reader_tx, reader_rx) = oneshot::channel();
+        if self.decision.send(reader_tx).is_err() {
+            return Err(Error::new(
+                "The connection was closed.",
+                SystemError::StreamRead,
+            ));
+        }
+        reader_rx.await.or(Err(Error::new(
+            "The connection was closed.",
+            SystemError::StreamRead,
+        )))
+    }
 }
 pub struct PersistentTcpConnection {
     remote_address: SocketAddr,
-    write_handle: Arc<Mutex<WriteHalf<TcpStream>>>,
+    write_handle: WriteHandle,
+    response_queue: ResponseQueueHandle,
     time_established: DateTime<Utc>,
     messages_count: u32,
+    max_messages: u32,
     listener_thread: JoinHandle<()>,
-    listener_receiver: Receiver<Vec<u8>>,
+    listener_receiver: Receiver<Result<TcpMessage, Error>>,
 }
 
 impl PersistentTcpConnection {
-    pub fn new(tcp_socket: TcpStream) -> Result<PersistentTcpConnection, Error> {
-        let socket_address = tcp_socket
-            .peer_addr()
-            .or(Err(Error::new("Failed to obtain remote address.", 4001)))?;
+    pub fn new(
+        tcp_socket: TcpStream,
+        limits: ConnectionLimits,
+    ) -> Result<PersistentTcpConnection, Error> {
+        let socket_address = tcp_socket.peer_addr().map_err(|e| {
+            Error::new("Failed to obtain remote address.", TcpError::RemoteAddress).with_source(e)
+        })?;
 
         // get read and write handles separetly, read goes to request, write goes to response
-        let (mut read_handle, write_handle) = tokio::io::split(tcp_socket);
+        let (read_handle, write_handle) = tokio::io::split(tcp_socket);
+
+        Ok(PersistentTcpConnection::from_parts(
+            socket_address,
+            Box::new(read_handle),
+            Box::new(write_handle),
+            limits,
+        ))
+    }
 
+    /// Creates a connection from already established read and write handles (ex.: after a TLS handshake).
+    pub fn from_parts(
+        socket_address: SocketAddr,
+        mut read_handle: Box<dyn ConnectionReader>,
+        write_handle: Box<dyn ConnectionWriter>,
+        limits: ConnectionLimits,
+    ) -> PersistentTcpConnection {
+        let max_messages = limits.max_requests;
         // create a channel to receieve data from a thread
         let (tx, rx) = mpsc::channel(TCP_MAX_MESSAGES as usize);
 
         // create a separate thread to listen for request so the connection thread is not blocked
         let listener_thread = tokio::spawn(async move {
-            // this loop keep the connection running after a request is received
+            // data is buffered until it contains a complete HTTP message, any remaining bytes belong to the next message
+            let mut msg_data = Vec::new();
+            let mut decoder =
+                MessageDecoder::with_limits(limits.max_header_size, limits.max_body_size);
+            // sender of the body which is currently being streamed
+            let mut body_tx: Option<mpsc::Sender<Result<Bytes, Error>>> = None;
+            // when the first byte of the current head was received
+            let mut head_started: Option<Instant> = None;
             loop {
-                // beaucause a tcp stream doesnt include an end character(like EOF), we try to read a buffer of a limited length in a loop, until there is no data left
-                let mut msg_data = Vec::new();
-                loop {
-                    let mut buffer = [0; BUFFER_SIZE];
-                    match read_handle.read(&mut buffer).await {
-                        Ok(len) => {
-                            msg_data = [msg_data, buffer[0..len].to_vec()].concat();
-                            if len < BUFFER_SIZE {
-                                break;
-                            }
+                // hand over every complete frame, multiple messages can arrive in one read
+                match decoder.decode(&mut msg_data) {
+                    Ok(Some(Frame::Message(message))) if is_upgrade_request(&message) => {
+                        let (decision_tx, decision_rx) = oneshot::channel();
+                        let upgrade = Upgrade {
+                            decision: decision_tx,
+                        };
+                        if tx
+                            .send(Ok(TcpMessage::new(message, None, Some(upgrade))))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                        head_started = None;
+                        // the rest of the data belongs to the new protocol if the upgrade is accepted
+                        if let Ok(reader_tx) = decision_rx.await {
+                            let _ = reader_tx.send(RawReader {
+                                reader: read_handle,
+                                buffered: msg_data,
+                            });
+                            return;
                         }
-                        Err(_) => {
+                        continue;
+                    }
+                    Ok(Some(Frame::Message(message))) => {
+                        head_started = None;
+                        if tx
+                            .send(Ok(TcpMessage::new(message, None, None)))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                        continue;
+                    }
+                    Ok(Some(Frame::Head(head))) => {
+                        let (chunk_tx, chunk_rx) = mpsc::channel(BODY_CHANNEL_SIZE);
+                        body_tx = Some(chunk_tx);
+                        head_started = None;
+                        if tx
+                            .send(Ok(TcpMessage::new(head, Some(chunk_rx), None)))
+                            .await
+                            .is_err()
+                        {
                             break;
                         }
+                        continue;
+                    }
+                    Ok(Some(Frame::Body(chunk))) => {
+                        // the rest of the body is discarded if the request was dropped without reading it
+                        if let Some(chunk_tx) = &body_tx {
+                            if chunk_tx.send(Ok(Bytes::from(chunk))).await.is_err() {
+                                body_tx = None;
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(Some(Frame::End)) => {
+                        body_tx = None;
+                        continue;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        report_error(&tx, body_tx.take(), e).await;
+                        break;
                     }
                 }
-                if msg_data.len() > 0 {
-                    if let Err(_) = tx.send(msg_data).await {}
+
+                // an idle connection waits for the next request, a started request has to keep coming
+                let timeout = if decoder.is_reading_body() {
+                    limits.body_timeout
+                } else if msg_data.is_empty() {
+                    limits.keep_alive_timeout
+                } else {
+                    let started = *head_started.get_or_insert_with(Instant::now);
+                    limits.header_timeout.saturating_sub(started.elapsed())
+                };
+                let mut buffer = [0; BUFFER_SIZE];
+                match time::timeout(timeout, read_handle.read(&mut buffer)).await {
+                    // the connection was closed
+                    Ok(Ok(0)) | Ok(Err(_)) => break,
+                    Ok(Ok(len)) => msg_data.extend_from_slice(&buffer[0..len]),
+                    // idle connections are closed without a response
+                    Err(_) if !decoder.is_reading_body() && msg_data.is_empty() => break,
+                    Err(_) => {
+                        let e = Error::new(
+                            "Timed out while receiving the request.",
+                            HttpError::Timeout,
+                        );
+                        report_error(&tx, body_tx.take(), e).await;
+                        break;
+                    }
                 }
             }
+            if let Some(chunk_tx) = body_tx {
+                let _ = chunk_tx
+                    .send(Err(Error::new(
+                        "Connection closed before the body was read.",
+                        SystemError::StreamRead,
+                    )))
+                    .await;
+            }
         });
-        let connection = PersistentTcpConnection {
+        let write_handle: WriteHandle = Arc::new(Mutex::new(write_handle));
+        PersistentTcpConnection {
             remote_address: socket_address,
-            write_handle: Arc::new(Mutex::new(write_handle)),
+            response_queue: Arc::new(Mutex::new(ResponseQueue::new(write_handle.clone()))),
+            write_handle,
             time_established: Utc::now(),
             messages_count: 0,
+            max_messages,
             listener_thread,
             listener_receiver: rx,
-        };
-        Ok(connection)
+        }
     }
+    /// Returns the next message, an `Error::Http` if the data received can't be read as a message.
     pub async fn messages(&mut self) -> Result<TcpMessage, Error> {
-        // if a message isnt received within n seconds the connection will be closed
-        if let Ok(raw_message) =
-            time::timeout(CONNECTION_STALLING_LIMIT, self.listener_receiver.recv()).await
-        {
-            if let Some(raw_message) = raw_message {
-                // keep track of the number of messages send on each connection
-                self.messages_count += 1;
-
-                if self.messages_count > TCP_MAX_MESSAGES {
-                    self.close().await?;
-                    return Err(Error::new(
-                        "Maximum number of messages per TCP connection was reached.",
-                        4002,
-                    ));
-                }
+        // the reader closes the channel once the connection is idle for too long
+        if let Some(message) = self.listener_receiver.recv().await {
+            let message = message?;
+            // keep track of the number of messages send on each connection
+            self.messages_count += 1;
 
-                let message = TcpMessage {
-                    time_received: Utc::now(),
-                    content: raw_message,
-                };
-                return Ok(message);
+            if self.messages_count > self.max_messages {
+                self.close().await?;
+                return Err(Error::new(
+                    "Maximum number of messages per TCP connection was reached.",
+                    TcpError::TooManyMessages,
+                ));
             }
-        } else {
-            self.close().await.unwrap();
-            return Err(Error::new("Connection stalling limit reached.", 4003));
-        }
-        Err(Error::new("Failed to read the message.", 1000))
-    }
-
-    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
-        let mut handle = self
-            .write_handle
-            .try_lock()
-            .or(Err(Error::new("Failed to obtain write lock.", 1000)))?;
 
-        handle
-            .write_all(data)
-            .await
-            .or(Err(Error::new("Failed to write to the stream.", 1003)))?;
-
-        if let Err(_) = handle.flush().await {
-            return Err(Error::new("Failed to flush the stream.", 1006));
-        };
-        Ok(())
+            return Ok(message);
+        }
+        Err(Error::new(
+            "Failed to read the message.",
+            SystemError::StreamRead,
+        ))
     }
 
     pub async fn close(&mut self) -> Result<(), Error> {
-        let mut handle = self
-            .write_handle
-            .try_lock()
-            .or(Err(Error::new("Failed to obtain write lock.", 1000)))?;
+        let mut handle = self.write_handle.try_lock().or(Err(Error::new(
+            "Failed to obtain write lock.",
+            SystemError::StreamWrite,
+        )))?;
 
         self.listener_thread.abort();
-        if let Err(_) = handle.shutdown().await {
-            return Err(Error::new("Failed to close the connection.", 1004));
-        }
-        Ok(())
+        handle.shutdown().await
     }
 
     pub fn remote_addr(&self) -> SocketAddr {
         self.remote_address
     }
 
-    pub fn get_write_handle(&self) -> &Arc<Mutex<WriteHalf<TcpStream>>> {
-        &self.write_handle
+    pub fn get_response_queue(&self) -> &ResponseQueueHandle {
+        &self.response_queue
     }
     pub fn get_message_count(&self) -> u32 {
         self.messages_count
     }
+    /// Whether the last message allowed on this connection was received.
+    pub fn is_last_message(&self) -> bool {
+        self.messages_count >= self.max_messages
+    }
+}
+
+// a broken body belongs to a request which is already being handled, otherwise the error is answered
+async fn report_error(
+    tx: &mpsc::Sender<Result<TcpMessage, Error>>,
+    body_tx: Option<mpsc::Sender<Result<Bytes, Error>>>,
+    error: Error,
+) {
+    match body_tx {
+        Some(chunk_tx) => {
+            let _ = chunk_tx.send(Err(error)).await;
+        }
+        None => {
+            let _ = tx.send(Err(error)).await;
+        }
+    }
 }
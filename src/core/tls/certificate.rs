@@ -0,0 +1,384 @@
+use std::sync::Arc;
+
+use ring::signature::{
+    EcdsaKeyPair, EcdsaSigningAlgorithm, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING,
+    ECDSA_P384_SHA384_ASN1_SIGNING,
+};
+
+use super::Certificate;
+use crate::error::TlsError;
+use crate::Error;
+
+// DER tags
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const CONTEXT_0: u8 = 0xa0;
+const CONTEXT_1: u8 = 0xa1;
+
+// named curves of SEC1 keys
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// The private key of the server certificate.
+#[derive(Debug, Clone)]
+pub enum PrivateKey {
+    Rsa(Arc<RsaKeyPair>),
+    Ecdsa(Arc<EcdsaKeyPair>, EcdsaCurve),
+}
+
+/// The named curve of an ECDSA key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EcdsaCurve {
+    P256,
+    P384,
+}
+
+impl EcdsaCurve {
+    fn algorithm(&self) -> &'static EcdsaSigningAlgorithm {
+        match self {
+            EcdsaCurve::P256 => &ECDSA_P256_SHA256_ASN1_SIGNING,
+            EcdsaCurve::P384 => &ECDSA_P384_SHA384_ASN1_SIGNING,
+        }
+    }
+}
+
+impl PrivateKey {
+    /// Reads a PEM or DER encoded PKCS#1 (RSA), PKCS#8 or SEC1 (EC) private key.
+    pub fn parse(data: &[u8]) -> Result<PrivateKey, Error> {
+        if !is_pem(data) {
+            return PrivateKey::from_der(data);
+        }
+        for (label, der) in pem_blocks(data)? {
+            match label.as_str() {
+                "PRIVATE KEY" => return PrivateKey::from_pkcs8(&der),
+                "RSA PRIVATE KEY" => return PrivateKey::from_pkcs1(&der),
+                "EC PRIVATE KEY" => return PrivateKey::from_sec1(&der),
+                "ENCRYPTED PRIVATE KEY" => {
+                    return Err(Error::new(
+                        "Encrypted private keys are not supported.",
+                        TlsError::InvalidPrivateKey,
+                    ))
+                }
+                // ex.: EC PARAMETERS written before the key by openssl
+                _ => continue,
+            }
+        }
+        Err(Error::new(
+            "The file does not contain a private key.",
+            TlsError::InvalidPrivateKey,
+        ))
+    }
+
+    fn from_der(der: &[u8]) -> Result<PrivateKey, Error> {
+        PrivateKey::from_pkcs8(der)
+            .or_else(|_| PrivateKey::from_pkcs1(der))
+            .or_else(|_| PrivateKey::from_sec1(der))
+            .or(Err(Error::new(
+                "The private key is not a PKCS#1, PKCS#8 or SEC1 key.",
+                TlsError::InvalidPrivateKey,
+            )))
+    }
+
+    fn from_pkcs8(der: &[u8]) -> Result<PrivateKey, Error> {
+        if let Ok(key_pair) = RsaKeyPair::from_pkcs8(der) {
+            return Ok(PrivateKey::Rsa(Arc::new(key_pair)));
+        }
+        for curve in [EcdsaCurve::P256, EcdsaCurve::P384] {
+            if let Ok(key_pair) = EcdsaKeyPair::from_pkcs8(curve.algorithm(), der) {
+                return Ok(PrivateKey::Ecdsa(Arc::new(key_pair), curve));
+            }
+        }
+        Err(Error::new(
+            "Invalid PKCS#8 private key.",
+            TlsError::InvalidPrivateKey,
+        ))
+    }
+
+    fn from_pkcs1(der: &[u8]) -> Result<PrivateKey, Error> {
+        let key_pair = RsaKeyPair::from_der(der).map_err(|e| {
+            Error::new("Invalid PKCS#1 private key.", TlsError::InvalidPrivateKey)
+                .with_source(e.to_string())
+        })?;
+        Ok(PrivateKey::Rsa(Arc::new(key_pair)))
+    }
+
+    // ECPrivateKey ::= SEQUENCE { version, privateKey, [0] parameters, [1] publicKey }
+    fn from_sec1(der: &[u8]) -> Result<PrivateKey, Error> {
+        let invalid = || Error::new("Invalid SEC1 private key.", TlsError::InvalidPrivateKey);
+        let (key, _) = read_tlv(der, SEQUENCE).ok_or_else(invalid)?;
+        let (_, key) = read_tlv(key, INTEGER).ok_or_else(invalid)?;
+        let (private_key, key) = read_tlv(key, OCTET_STRING).ok_or_else(invalid)?;
+        let (parameters, key) = read_tlv(key, CONTEXT_0).ok_or_else(invalid)?;
+        let (curve, _) = read_tlv(parameters, OID).ok_or_else(invalid)?;
+        // ring derives nothing, the public key has to be part of the file
+        let (public_key, _) = read_tlv(key, CONTEXT_1)
+            .and_then(|(public_key, _)| read_tlv(public_key, BIT_STRING))
+            .ok_or(Error::new(
+                "The SEC1 private key has no public key.",
+                TlsError::InvalidPrivateKey,
+            ))?;
+        let public_key = public_key.get(1..).ok_or_else(invalid)?;
+
+        let curve = match curve {
+            OID_P256 => EcdsaCurve::P256,
+            OID_P384 => EcdsaCurve::P384,
+            _ => {
+                return Err(Error::new(
+                    "Unsupported elliptic curve.",
+                    TlsError::InvalidPrivateKey,
+                ))
+            }
+        };
+        let key_pair = EcdsaKeyPair::from_private_key_and_public_key(
+            curve.algorithm(),
+            private_key,
+            public_key,
+        )
+        .map_err(|e| invalid().with_source(e.to_string()))?;
+        Ok(PrivateKey::Ecdsa(Arc::new(key_pair), curve))
+    }
+
+    /// The public key in the form it is stored in a certificate.
+    fn public_key(&self) -> &[u8] {
+        match self {
+            PrivateKey::Rsa(key_pair) => key_pair.public_key().as_ref(),
+            PrivateKey::Ecdsa(key_pair, _) => key_pair.public_key().as_ref(),
+        }
+    }
+
+    /// Fails if the key doesn't belong to the certificate.
+    pub fn verify_matches(&self, certificate: &Certificate) -> Result<(), Error> {
+        if certificate_public_key(&certificate.raw)? != self.public_key() {
+            return Err(Error::new(
+                "The private key does not match the certificate.",
+                TlsError::KeyMismatch,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads a PEM or DER encoded certificate chain, the leaf certificate comes first.
+pub fn parse_certificates(data: &[u8]) -> Result<Vec<Certificate>, Error> {
+    let certificates: Vec<Certificate> = if is_pem(data) {
+        pem_blocks(data)?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, raw)| Certificate { raw })
+            .collect()
+    } else {
+        // concatenated DER certificates
+        let mut certificates = vec![];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let length = data.len() - rest.len();
+            let (_, next) = read_tlv(rest, SEQUENCE).ok_or(Error::new(
+                "Invalid DER certificate.",
+                TlsError::InvalidCertificate,
+            ))?;
+            let raw = data[length..(data.len() - next.len())].to_vec();
+            certificates.push(Certificate { raw });
+            rest = next;
+        }
+        certificates
+    };
+
+    if certificates.is_empty() {
+        return Err(Error::new(
+            "The file does not contain a certificate.",
+            TlsError::InvalidCertificate,
+        ));
+    }
+    for certificate in &certificates {
+        certificate_public_key(&certificate.raw)?;
+    }
+    Ok(certificates)
+}
+
+// Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signature }
+// TBSCertificate ::= SEQUENCE { [0] version, serialNumber, signature, issuer, validity, subject,
+//                               subjectPublicKeyInfo, ... }
+fn certificate_public_key(der: &[u8]) -> Result<&[u8], Error> {
+    let invalid = || Error::new("Invalid DER certificate.", TlsError::InvalidCertificate);
+    let (certificate, _) = read_tlv(der, SEQUENCE).ok_or_else(invalid)?;
+    let (mut tbs, _) = read_tlv(certificate, SEQUENCE).ok_or_else(invalid)?;
+    // the version is omitted in v1 certificates
+    if let Some((_, rest)) = read_tlv(tbs, CONTEXT_0) {
+        tbs = rest;
+    }
+    let (_, tbs) = read_tlv(tbs, INTEGER).ok_or_else(invalid)?;
+    // signature, issuer, validity, subject
+    let mut rest = tbs;
+    for _ in 0..4 {
+        rest = read_tlv(rest, SEQUENCE).ok_or_else(invalid)?.1;
+    }
+    let (public_key_info, _) = read_tlv(rest, SEQUENCE).ok_or_else(invalid)?;
+    let (_, public_key_info) = read_tlv(public_key_info, SEQUENCE).ok_or_else(invalid)?;
+    let (public_key, _) = read_tlv(public_key_info, BIT_STRING).ok_or_else(invalid)?;
+    // the first byte is the number of unused bits
+    public_key.get(1..).ok_or_else(invalid)
+}
+
+/// Reads one DER element with the expected tag, returns its content and the remaining data.
+fn read_tlv(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    if *data.first()? != tag {
+        return None;
+    }
+    let first = *data.get(1)? as usize;
+    let (length, offset) = if first < 0x80 {
+        (first, 2)
+    } else {
+        // long form, the low bits are the number of length bytes
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let length = data
+            .get(2..(2 + count))?
+            .iter()
+            .fold(0usize, |acc, byte| (acc << 8) | *byte as usize);
+        (length, 2 + count)
+    };
+    let end = offset.checked_add(length)?;
+    Some((data.get(offset..end)?, &data[end..]))
+}
+
+fn is_pem(data: &[u8]) -> bool {
+    data.windows(11).any(|window| window == b"-----BEGIN ")
+}
+
+/// Returns the label and decoded content of every PEM block.
+fn pem_blocks(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let text = std::str::from_utf8(data).map_err(|e| {
+        Error::new("Invalid PEM file.", TlsError::InvalidCertificate).with_source(e)
+    })?;
+    let mut blocks = vec![];
+    let mut current: Option<(String, String)> = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|line| line.strip_suffix("-----"))
+        {
+            current = Some((label.to_owned(), String::new()));
+        } else if let Some(label) = line
+            .strip_prefix("-----END ")
+            .and_then(|line| line.strip_suffix("-----"))
+        {
+            match current.take() {
+                Some((begin, content)) if begin == label => {
+                    let der = base64::decode(&content).map_err(|e| {
+                        Error::new("Invalid PEM file.", TlsError::InvalidCertificate).with_source(e)
+                    })?;
+                    blocks.push((begin, der));
+                }
+                _ => {
+                    return Err(Error::new(
+                        "Invalid PEM file.",
+                        TlsError::InvalidCertificate,
+                    ))
+                }
+            }
+        } else if let Some((_, content)) = &mut current {
+            // headers of legacy blocks (ex.: Proc-Type) are not base64
+            if line.starts_with("Proc-Type:") && line.contains("ENCRYPTED") {
+                return Err(Error::new(
+                    "Encrypted private keys are not supported.",
+                    TlsError::InvalidPrivateKey,
+                ));
+            } else if !line.contains(':') {
+                content.push_str(line);
+            }
+        }
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/tls/",
+                $name
+            ))
+        };
+    }
+
+    #[test]
+    fn parses_pem_chain_with_leaf_first() {
+        let chain = parse_certificates(fixture!("ec_chain.pem")).unwrap();
+        let intermediate = parse_certificates(fixture!("intermediate.pem")).unwrap();
+
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[0].raw, fixture!("ec_leaf.der"));
+        assert_eq!(chain[1].raw, intermediate[0].raw);
+    }
+
+    #[test]
+    fn parses_der_certificate() {
+        let certificates = parse_certificates(fixture!("ec_leaf.der")).unwrap();
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(certificates[0].raw, fixture!("ec_leaf.der"));
+    }
+
+    #[test]
+    fn parses_each_key_format() {
+        let ec_leaf = &parse_certificates(fixture!("ec_leaf.pem")).unwrap()[0];
+        let rsa_leaf = &parse_certificates(fixture!("rsa_leaf.pem")).unwrap()[0];
+
+        let sec1 = PrivateKey::parse(fixture!("ec_key_sec1.pem")).unwrap();
+        assert!(matches!(sec1, PrivateKey::Ecdsa(_, EcdsaCurve::P256)));
+        sec1.verify_matches(ec_leaf).unwrap();
+
+        let pkcs8 = PrivateKey::parse(fixture!("ec_key_pkcs8.pem")).unwrap();
+        assert!(matches!(pkcs8, PrivateKey::Ecdsa(_, EcdsaCurve::P256)));
+        pkcs8.verify_matches(ec_leaf).unwrap();
+
+        let pkcs1 = PrivateKey::parse(fixture!("rsa_key_pkcs1.pem")).unwrap();
+        assert!(matches!(pkcs1, PrivateKey::Rsa(_)));
+        pkcs1.verify_matches(rsa_leaf).unwrap();
+    }
+
+    #[test]
+    fn rejects_mismatched_key() {
+        let ec_leaf = &parse_certificates(fixture!("ec_leaf.pem")).unwrap()[0];
+        let rsa_leaf = &parse_certificates(fixture!("rsa_leaf.pem")).unwrap()[0];
+        let intermediate = &parse_certificates(fixture!("intermediate.pem")).unwrap()[0];
+        let ec_key = PrivateKey::parse(fixture!("ec_key_sec1.pem")).unwrap();
+        let rsa_key = PrivateKey::parse(fixture!("rsa_key_pkcs1.pem")).unwrap();
+
+        for (key, certificate) in [
+            (&ec_key, rsa_leaf),
+            (&rsa_key, ec_leaf),
+            (&ec_key, intermediate),
+        ] {
+            let result = key.verify_matches(certificate);
+            assert!(matches!(
+                result.map_err(|e| e.kind()),
+                Err(ErrorKind::Tls(TlsError::KeyMismatch))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let result = parse_certificates(fixture!("ec_key_pkcs8.pem"));
+        assert!(matches!(
+            result.map_err(|e| e.kind()),
+            Err(ErrorKind::Tls(TlsError::InvalidCertificate))
+        ));
+
+        let result = PrivateKey::parse(fixture!("ec_leaf.pem"));
+        assert!(matches!(
+            result.map_err(|e| e.kind()),
+            Err(ErrorKind::Tls(TlsError::InvalidPrivateKey))
+        ));
+    }
+}
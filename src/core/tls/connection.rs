@@ -0,0 +1,565 @@
+use crate::core::tcp::{ConnectionReader, ConnectionWriter};
+use crate::error::{ErrorKind, SystemError, TcpError, TlsError};
+use crate::{join_bytes, Error};
+use async_trait::async_trait;
+use ring::constant_time;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use super::crypto::ECCurve;
+use super::protocol::{HandshakeMessage, TLSMessage, TLSSession};
+use super::record::RecordLayer;
+use super::signature;
+use super::{KeyExchange, TLSAlert, TLSConfig, TLSExtension, TLSRecord, TLSVersion};
+
+// secure renegotiation (RFC 5746)
+const RENEGOTIATION_INFO: u16 = 0xff01;
+const RENEGOTIATION_SCSV: u16 = 0x00ff;
+// RFC 7627
+const EXTENDED_MASTER_SECRET: u16 = 0x0017;
+// RFC 8422
+const SUPPORTED_GROUPS: u16 = 0x000a;
+const X25519: u16 = 0x001d;
+const BUFFER_SIZE: usize = 8192;
+const MAX_HANDSHAKE_MESSAGE_LENGTH: usize = 65536;
+
+/// A TCP connection which completed the TLS handshake.
+pub struct TLSConnection {
+    remote_address: SocketAddr,
+    records: Arc<Mutex<RecordLayer>>,
+    read_handle: ReadHalf<TcpStream>,
+    write_handle: WriteHalf<TcpStream>,
+}
+
+impl TLSConnection {
+    /// Drives the server side of the handshake on a freshly accepted socket.
+    pub async fn accept(
+        tcp_socket: TcpStream,
+        config: Arc<TLSConfig>,
+    ) -> Result<TLSConnection, Error> {
+        let remote_address = tcp_socket.peer_addr().map_err(|e| {
+            Error::new("Failed to obtain remote address.", TcpError::RemoteAddress).with_source(e)
+        })?;
+        let (mut read_handle, mut write_handle) = tokio::io::split(tcp_socket);
+
+        // the cipher suite is replaced by the negotiated one
+        let mut records = RecordLayer::new(TLSSession::new(config.cipher_suites()[0]));
+        let mut handshake_buffer: Vec<u8> = vec![];
+
+        // CLIENT HELLO
+        let (message, raw) = receive_handshake_message(
+            &mut read_handle,
+            &mut write_handle,
+            &mut records,
+            &mut handshake_buffer,
+        )
+        .await?;
+        let (client_random, client_extensions, secure_renegotiation) = match message {
+            HandshakeMessage::ClientHello {
+                random,
+                cipher_suites,
+                extensions,
+                ..
+            } => {
+                records.session.cipher_suite = match config.select_cipher_suite(&cipher_suites) {
+                    Some(suite) => suite,
+                    None => {
+                        send_alert(&mut write_handle, &mut records, TLSAlert::HandshakeFailure)
+                            .await;
+                        return Err(Error::new(
+                            "No supported cipher suite was offered.",
+                            TlsError::HandshakeFailure,
+                        ));
+                    }
+                };
+                // x25519 is the only curve of the key exchange
+                if !offers_x25519(&extensions) {
+                    send_alert(&mut write_handle, &mut records, TLSAlert::HandshakeFailure).await;
+                    return Err(Error::new(
+                        "The client doesn't support the x25519 curve.",
+                        TlsError::HandshakeFailure,
+                    ));
+                }
+                // the client announces secure renegotiation with the extension or the SCSV cipher suite
+                let secure_renegotiation = cipher_suites.contains(&RENEGOTIATION_SCSV)
+                    || extensions.iter().any(|ext| ext.id == RENEGOTIATION_INFO);
+                records.session.extended_master_secret = extensions
+                    .iter()
+                    .any(|ext| ext.id == EXTENDED_MASTER_SECRET);
+                (random, extensions, secure_renegotiation)
+            }
+            _ => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::UnexpectedMessage).await;
+                return Err(Error::new(
+                    "Expected a ClientHello message.",
+                    TlsError::HandshakeFailure,
+                ));
+            }
+        };
+        records.session.client_random = Some(client_random.clone());
+        records
+            .session
+            .handshake_messages
+            .push(handshake_record(raw));
+
+        // the ECDHE parameters are signed together with both randoms
+        let key_exchange = KeyExchange::ECDHE {
+            curve: ECCurve::x25519,
+            public_key: records
+                .session
+                .ephemeral_pair
+                .public_key()
+                .to_bytes()
+                .to_vec(),
+        };
+        let signed = [
+            &client_random[..],
+            &records.session.server_random[..],
+            &key_exchange.params()?[..],
+        ]
+        .concat();
+        let signature = match signature::sign(
+            &config.private_key,
+            &signature::offered_schemes(&client_extensions),
+            &signed,
+        ) {
+            Ok(signature) => signature,
+            Err(e) => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::HandshakeFailure).await;
+                return Err(e);
+            }
+        };
+
+        // an empty renegotiation_info, renegotiation itself is not supported
+        let mut server_extensions = vec![];
+        if secure_renegotiation {
+            server_extensions.push(TLSExtension::new(RENEGOTIATION_INFO, vec![0x00]));
+        }
+        if records.session.extended_master_secret {
+            server_extensions.push(TLSExtension::new(EXTENDED_MASTER_SECRET, vec![]));
+        }
+
+        // SERVER HELLO, CERTIFICATE, SERVER KEY EXCHANGE, SERVER HELLO DONE
+        let server_messages = [
+            HandshakeMessage::ServerHello {
+                version: TLSVersion::TLS1_2,
+                random: records.session.server_random.to_vec(),
+                session_id: None,
+                cipher_suite: records.session.cipher_suite,
+                extensions: server_extensions,
+            },
+            HandshakeMessage::ServerCertificate {
+                certificates: config.certificates.clone(),
+            },
+            HandshakeMessage::ServerKeyExchange {
+                key_exchange,
+                signature,
+            },
+            HandshakeMessage::ServerHelloDone,
+        ];
+        let mut flight = vec![];
+        for message in &server_messages {
+            let raw = message.get_raw()?;
+            flight.extend(records.seal(TLSRecord::Handshake, &raw)?);
+            records
+                .session
+                .handshake_messages
+                .push(handshake_record(raw));
+        }
+        write_all(&mut write_handle, &flight).await?;
+
+        // CLIENT KEY EXCHANGE
+        let (message, raw) = receive_handshake_message(
+            &mut read_handle,
+            &mut write_handle,
+            &mut records,
+            &mut handshake_buffer,
+        )
+        .await?;
+        let client_public_key = match message {
+            HandshakeMessage::ClientKeyExchange { public_key } => public_key,
+            _ => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::UnexpectedMessage).await;
+                return Err(Error::new(
+                    "Expected a ClientKeyExchange message.",
+                    TlsError::HandshakeFailure,
+                ));
+            }
+        };
+        let client_public_key: [u8; 32] = match client_public_key.try_into() {
+            Ok(client_public_key) => client_public_key,
+            Err(_) => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::DecodeError).await;
+                return Err(Error::new(
+                    "Invalid public key length.",
+                    TlsError::InvalidField,
+                ));
+            }
+        };
+        records.session.client_public_key = Some(client_public_key);
+        records
+            .session
+            .handshake_messages
+            .push(handshake_record(raw));
+        records.session.calculate_encryption_keys()?;
+
+        // CHANGE CIPHER SPEC, the handshake messages can't continue over it
+        if !handshake_buffer.is_empty() {
+            send_alert(&mut write_handle, &mut records, TLSAlert::UnexpectedMessage).await;
+            return Err(Error::new(
+                "Expected a ChangeCipherSpec message.",
+                TlsError::HandshakeFailure,
+            ));
+        }
+        match read_record(&mut read_handle, &mut records).await {
+            Ok(Some(TLSMessage {
+                record: TLSRecord::ChangeCipherSpec,
+                ..
+            })) => records.change_read_cipher(),
+            _ => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::UnexpectedMessage).await;
+                return Err(Error::new(
+                    "Expected a ChangeCipherSpec message.",
+                    TlsError::HandshakeFailure,
+                ));
+            }
+        }
+
+        // CLIENT FINISHED (encrypted)
+        let raw = match read_record(&mut read_handle, &mut records).await {
+            Ok(Some(TLSMessage {
+                record: TLSRecord::Handshake,
+                content,
+                ..
+            })) => content,
+            // the record failed to authenticate
+            Err(
+                e @ Error::Tls {
+                    kind: TlsError::Crypto,
+                    ..
+                },
+            ) => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::BadRecordMac).await;
+                return Err(e);
+            }
+            _ => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::UnexpectedMessage).await;
+                return Err(Error::new(
+                    "Expected a Finished message.",
+                    TlsError::HandshakeFailure,
+                ));
+            }
+        };
+        let verify_data = match HandshakeMessage::parse(&raw) {
+            Ok(HandshakeMessage::HandshakeFinished { verify_data }) => verify_data,
+            Err(e) => {
+                send_alert(&mut write_handle, &mut records, handshake_alert(&e)).await;
+                return Err(e);
+            }
+            _ => {
+                send_alert(&mut write_handle, &mut records, TLSAlert::UnexpectedMessage).await;
+                return Err(Error::new(
+                    "Expected a Finished message.",
+                    TlsError::HandshakeFailure,
+                ));
+            }
+        };
+        // the client has to arrive at the same transcript, otherwise the handshake was tampered with
+        let expected = records.session.calculate_verify_data(b"client finished")?;
+        if constant_time::verify_slices_are_equal(&expected, &verify_data).is_err() {
+            send_alert(&mut write_handle, &mut records, TLSAlert::DecryptError).await;
+            return Err(Error::new(
+                "The client Finished message is invalid.",
+                TlsError::HandshakeFailure,
+            ));
+        }
+        records
+            .session
+            .handshake_messages
+            .push(handshake_record(raw));
+
+        // SERVER CHANGE CIPHER SPEC, SERVER FINISHED
+        let verify_data = records.session.calculate_verify_data(b"server finished")?;
+        let finished = HandshakeMessage::HandshakeFinished { verify_data }.get_raw()?;
+
+        let mut flight = records.seal(TLSRecord::ChangeCipherSpec, &[0x01])?;
+        records.change_write_cipher();
+        flight.extend(records.seal(TLSRecord::Handshake, &finished)?);
+        write_all(&mut write_handle, &flight).await?;
+
+        Ok(TLSConnection {
+            remote_address,
+            records: Arc::new(Mutex::new(records)),
+            read_handle,
+            write_handle,
+        })
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_address
+    }
+
+    /// Splits the connection into handles which decrypt incoming and encrypt outgoing application data.
+    pub fn split(self) -> (TLSReader, TLSWriter) {
+        (
+            TLSReader {
+                records: self.records.clone(),
+                read_handle: self.read_handle,
+                plaintext: vec![],
+            },
+            TLSWriter {
+                records: self.records,
+                write_handle: self.write_handle,
+            },
+        )
+    }
+}
+
+pub struct TLSReader {
+    records: Arc<Mutex<RecordLayer>>,
+    read_handle: ReadHalf<TcpStream>,
+    plaintext: Vec<u8>,
+}
+
+#[async_trait]
+impl ConnectionReader for TLSReader {
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        while self.plaintext.is_empty() {
+            // the lock is released while waiting for data so the writer isn't blocked
+            let message = self.records.lock().await.open()?;
+            let message = match message {
+                Some(message) => message,
+                None => {
+                    let mut buffer = [0u8; BUFFER_SIZE];
+                    let has_partial_record = self.records.lock().await.has_partial_record();
+                    let length =
+                        read_some(&mut self.read_handle, &mut buffer, has_partial_record).await?;
+                    if length == 0 {
+                        return Ok(0);
+                    }
+                    self.records.lock().await.receive(&buffer[..length]);
+                    continue;
+                }
+            };
+            match message.record {
+                TLSRecord::Application => self.plaintext.extend(message.content),
+                // both close_notify and fatal alerts end the connection
+                TLSRecord::Alert => return Ok(0),
+                _ => return Err(Error::new("Unexpected message.", TlsError::InvalidMessage)),
+            }
+        }
+
+        let length = buffer.len().min(self.plaintext.len());
+        buffer[..length].copy_from_slice(&self.plaintext[..length]);
+        self.plaintext.drain(..length);
+        Ok(length)
+    }
+}
+
+pub struct TLSWriter {
+    records: Arc<Mutex<RecordLayer>>,
+    write_handle: WriteHalf<TcpStream>,
+}
+
+impl std::fmt::Debug for TLSWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TLSWriter").finish()
+    }
+}
+
+#[async_trait]
+impl ConnectionWriter for TLSWriter {
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let raw = self
+            .records
+            .lock()
+            .await
+            .seal(TLSRecord::Application, data)?;
+        write_all(&mut self.write_handle, &raw).await
+    }
+
+    async fn shutdown(&mut self) -> Result<(), Error> {
+        let raw = self
+            .records
+            .lock()
+            .await
+            .seal(TLSRecord::Alert, &TLSAlert::CloseNotify.get_value())?;
+        write_all(&mut self.write_handle, &raw).await?;
+
+        if let Err(e) = AsyncWriteExt::shutdown(&mut self.write_handle).await {
+            return Err(Error::new(
+                "Failed to close the connection.",
+                SystemError::StreamShutdown,
+            )
+            .with_source(e));
+        }
+        Ok(())
+    }
+}
+
+// alerts during the handshake are sent in plaintext, the server didn't change its cipher spec yet
+async fn send_alert(
+    write_handle: &mut WriteHalf<TcpStream>,
+    records: &mut RecordLayer,
+    alert: TLSAlert,
+) {
+    if let Ok(raw) = records.seal(TLSRecord::Alert, &alert.get_value()) {
+        let _ = write_all(write_handle, &raw).await;
+    }
+}
+
+fn handshake_record(raw: Vec<u8>) -> TLSMessage {
+    TLSMessage::new(TLSRecord::Handshake, TLSVersion::TLS1_2, raw)
+}
+
+async fn write_all(write_handle: &mut WriteHalf<TcpStream>, data: &[u8]) -> Result<(), Error> {
+    write_handle.write_all(data).await.map_err(|e| {
+        Error::new("Failed to write to the stream.", SystemError::StreamWrite).with_source(e)
+    })?;
+    if let Err(e) = write_handle.flush().await {
+        return Err(
+            Error::new("Failed to flush the stream.", SystemError::StreamFlush).with_source(e),
+        );
+    };
+    Ok(())
+}
+
+/// Reads until a whole record was received, returns None if the connection was closed.
+async fn read_record(
+    read_handle: &mut ReadHalf<TcpStream>,
+    records: &mut RecordLayer,
+) -> Result<Option<TLSMessage>, Error> {
+    loop {
+        if let Some(message) = records.open()? {
+            return Ok(Some(message));
+        }
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let length = read_some(read_handle, &mut buffer, records.has_partial_record()).await?;
+        if length == 0 {
+            return Ok(None);
+        }
+        records.receive(&buffer[..length]);
+    }
+}
+
+// returns 0 once the connection was closed between records
+async fn read_some(
+    read_handle: &mut ReadHalf<TcpStream>,
+    buffer: &mut [u8],
+    has_partial_record: bool,
+) -> Result<usize, Error> {
+    let length = AsyncReadExt::read(read_handle, buffer).await.map_err(|e| {
+        Error::new("Failed to read from the stream.", SystemError::StreamRead).with_source(e)
+    })?;
+    if length == 0 && has_partial_record {
+        return Err(Error::new(
+            "The connection was closed in the middle of a record.",
+            SystemError::StreamRead,
+        ));
+    }
+    Ok(length)
+}
+
+/// Reads and parses the next handshake message, the client is sent an alert if that fails.
+async fn receive_handshake_message(
+    read_handle: &mut ReadHalf<TcpStream>,
+    write_handle: &mut WriteHalf<TcpStream>,
+    records: &mut RecordLayer,
+    buffer: &mut Vec<u8>,
+) -> Result<(HandshakeMessage, Vec<u8>), Error> {
+    let message = match read_handshake_message(read_handle, records, buffer).await {
+        Ok(raw) => HandshakeMessage::parse(&raw).map(|message| (message, raw)),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &message {
+        send_alert(write_handle, records, handshake_alert(e)).await;
+    }
+    message
+}
+
+// clients which don't send the supported_groups extension accept any curve
+fn offers_x25519(extensions: &[TLSExtension]) -> bool {
+    match extensions.iter().find(|ext| ext.id == SUPPORTED_GROUPS) {
+        // 2 bytes list length followed by 2 bytes per group
+        Some(extension) => extension
+            .content
+            .get(2..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .any(|group| group == X25519.to_be_bytes()),
+        None => true,
+    }
+}
+
+// the alert describing why the handshake failed
+fn handshake_alert(error: &Error) -> TLSAlert {
+    match error.kind() {
+        ErrorKind::Tls(TlsError::InvalidMessage | TlsError::InvalidField) => TLSAlert::DecodeError,
+        ErrorKind::Tls(TlsError::UnknownMessage) => TLSAlert::UnexpectedMessage,
+        ErrorKind::Tls(TlsError::Crypto) => TLSAlert::BadRecordMac,
+        _ => TLSAlert::HandshakeFailure,
+    }
+}
+
+/// Reads records until a whole handshake message is buffered, handshake messages can be split over or share records.
+async fn read_handshake_message(
+    read_handle: &mut ReadHalf<TcpStream>,
+    records: &mut RecordLayer,
+    buffer: &mut Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    loop {
+        // message type (1 byte) + length (3 bytes)
+        if buffer.len() >= 4 {
+            let length = 4 + join_bytes(&buffer[1..4])? as usize;
+            // the client's messages are small, the length can't make the server buffer megabytes
+            if length > MAX_HANDSHAKE_MESSAGE_LENGTH {
+                return Err(Error::new(
+                    "The handshake message is too large.",
+                    TlsError::InvalidMessage,
+                ));
+            }
+            if buffer.len() >= length {
+                return Ok(buffer.drain(..length).collect());
+            }
+        }
+        match read_record(read_handle, records).await? {
+            Some(TLSMessage {
+                record: TLSRecord::Handshake,
+                content,
+                ..
+            }) => buffer.extend(content),
+            Some(_) => return Err(Error::new("Unexpected message.", TlsError::InvalidMessage)),
+            None => {
+                return Err(Error::new(
+                    "Connection closed during the handshake.",
+                    TlsError::HandshakeFailure,
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_offered_groups() {
+        // secp256r1 and x25519
+        let both = TLSExtension::new(SUPPORTED_GROUPS, vec![0x00, 0x04, 0x00, 0x17, 0x00, 0x1d]);
+        assert!(offers_x25519(&[both]));
+
+        let secp256r1 = TLSExtension::new(SUPPORTED_GROUPS, vec![0x00, 0x02, 0x00, 0x17]);
+        assert!(!offers_x25519(&[secp256r1]));
+        assert!(!offers_x25519(&[TLSExtension::new(
+            SUPPORTED_GROUPS,
+            vec![]
+        )]));
+
+        assert!(offers_x25519(&[]));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::TlsError;
 use crate::Error;
 use crypto::aead::{AeadDecryptor, AeadEncryptor};
 use crypto::aes_gcm::AesGcm;
@@ -54,7 +55,7 @@ impl EncryptedMessage {
     /// This function encrypts the data passed to it.
     /// Returns: encrypted message in bytes
     pub fn encrypt(data: &[u8], encryption_iv: &[u8], encrypt_key: &[u8], aad: &[u8]) -> Vec<u8> {
-        let mut cipher = AesGcm::new(KeySize::KeySize128, encrypt_key, encryption_iv, aad);
+        let mut cipher = AesGcm::new(key_size(encrypt_key), encrypt_key, encryption_iv, aad);
 
         let mut enc = vec![0; data.len()];
         let mut tag = vec![0; 16];
@@ -68,7 +69,7 @@ impl EncryptedMessage {
         decrypt_key: &[u8],
         aad: &[u8],
     ) -> Result<Vec<u8>, Error> {
-        let mut cipher = AesGcm::new(KeySize::KeySize128, decrypt_key, encryption_iv, aad);
+        let mut cipher = AesGcm::new(key_size(decrypt_key), decrypt_key, encryption_iv, aad);
 
         let tag = &data[(data.len() - 16)..];
         let mut dec = vec![0; data.len() - 16];
@@ -77,7 +78,18 @@ impl EncryptedMessage {
         if decrypted {
             Ok(dec)
         } else {
-            Err(Error::new("Failed to decrypt the message.", 5006))
+            Err(Error::new(
+                "Failed to decrypt the message.",
+                TlsError::Crypto,
+            ))
         }
     }
 }
+
+// AES-128 or AES-256, decided by the cipher suite's key length
+fn key_size(key: &[u8]) -> KeySize {
+    match key.len() {
+        32 => KeySize::KeySize256,
+        _ => KeySize::KeySize128,
+    }
+}
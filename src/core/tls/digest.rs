@@ -10,6 +10,8 @@ impl HmacSha256 {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum DigestAlgorithm {
     SHA256,
+    SHA384,
 }
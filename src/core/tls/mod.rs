@@ -1,9 +1,17 @@
+use self::certificate::{parse_certificates, PrivateKey};
 use self::crypto::ECCurve;
+use self::digest::DigestAlgorithm;
+use crate::error::{SystemError, TlsError};
 use crate::Error;
+use std::path::Path;
 
+pub mod certificate;
+pub mod connection;
 pub mod crypto;
 pub mod digest;
+pub mod prf;
 pub mod protocol;
+pub mod record;
 pub mod signature;
 
 #[derive(Debug, Clone)]
@@ -11,6 +19,64 @@ pub struct Certificate {
     pub raw: Vec<u8>,
 }
 
+/// Certificates and the private key used by the server during the handshake.
+#[derive(Debug, Clone)]
+pub struct TLSConfig {
+    /// The leaf certificate followed by its intermediates.
+    pub certificates: Vec<Certificate>,
+    pub private_key: PrivateKey,
+}
+
+impl TLSConfig {
+    /// Reads a PEM or DER encoded certificate chain and private key, fails if they don't belong together.
+    pub fn load(certificate: &Path, private_key: &Path) -> Result<TLSConfig, Error> {
+        let certificates = std::fs::read(certificate).map_err(|e| {
+            Error::new(
+                "Failed to read the certificate file.",
+                SystemError::FileRead,
+            )
+            .with_source(e)
+        })?;
+        let private_key = std::fs::read(private_key).map_err(|e| {
+            Error::new(
+                "Failed to read the private key file.",
+                SystemError::FileRead,
+            )
+            .with_source(e)
+        })?;
+
+        let certificates = parse_certificates(&certificates)?;
+        let private_key = PrivateKey::parse(&private_key)?;
+        private_key.verify_matches(&certificates[0])?;
+
+        Ok(TLSConfig {
+            certificates,
+            private_key,
+        })
+    }
+
+    /// The cipher suites which can be authenticated with the private key, most preferred first.
+    pub fn cipher_suites(&self) -> [CipherSuite; 2] {
+        match self.private_key {
+            PrivateKey::Rsa(_) => [
+                CipherSuite::EcdheRsaAes128GcmSha256,
+                CipherSuite::EcdheRsaAes256GcmSha384,
+            ],
+            PrivateKey::Ecdsa(..) => [
+                CipherSuite::EcdheEcdsaAes128GcmSha256,
+                CipherSuite::EcdheEcdsaAes256GcmSha384,
+            ],
+        }
+    }
+
+    /// Picks the most preferred cipher suite the client offered.
+    pub fn select_cipher_suite(&self, offered: &[u16]) -> Option<CipherSuite> {
+        self.cipher_suites()
+            .into_iter()
+            .find(|suite| offered.contains(&suite.value()))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TLSExtension {
     id: u16,
@@ -28,15 +94,77 @@ pub enum KeyExchange {
     ECDHE { curve: ECCurve, public_key: Vec<u8> },
 }
 
-#[derive(Debug, Clone)]
+impl KeyExchange {
+    /// The parameters sent in the ServerKeyExchange message, covered by its signature.
+    pub fn params(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            KeyExchange::ECDHE { curve, public_key } => {
+                if public_key.len() > u8::MAX as usize {
+                    return Err(Error::new(
+                        "Invalid public key. (max length 255 bytes)",
+                        TlsError::InvalidField,
+                    ));
+                }
+                // named curve
+                let mut params = vec![0x03];
+                match curve {
+                    // assigned x25519 value
+                    ECCurve::x25519 => params.extend(&[0x00, 0x1d]),
+                }
+                // public key with its length
+                params.push(public_key.len() as u8);
+                params.extend(public_key);
+                Ok(params)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CipherSuite {
-    TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    /// TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+    EcdheRsaAes128GcmSha256,
+    /// TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+    EcdheRsaAes256GcmSha384,
+    /// TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+    EcdheEcdsaAes128GcmSha256,
+    /// TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+    EcdheEcdsaAes256GcmSha384,
 }
 
 impl CipherSuite {
     fn bytes(&self) -> [u8; 2] {
         match self {
-            CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 => [0xc0, 0x2f],
+            CipherSuite::EcdheRsaAes128GcmSha256 => [0xc0, 0x2f],
+            CipherSuite::EcdheRsaAes256GcmSha384 => [0xc0, 0x30],
+            CipherSuite::EcdheEcdsaAes128GcmSha256 => [0xc0, 0x2b],
+            CipherSuite::EcdheEcdsaAes256GcmSha384 => [0xc0, 0x2c],
+        }
+    }
+    /// The assigned value as it appears in the cipher_suites list of the ClientHello.
+    pub fn value(&self) -> u16 {
+        u16::from_be_bytes(self.bytes())
+    }
+    /// The hash the PRF of this cipher suite is based on.
+    pub fn prf_hash(&self) -> DigestAlgorithm {
+        match self {
+            CipherSuite::EcdheRsaAes128GcmSha256 | CipherSuite::EcdheEcdsaAes128GcmSha256 => {
+                DigestAlgorithm::SHA256
+            }
+            CipherSuite::EcdheRsaAes256GcmSha384 | CipherSuite::EcdheEcdsaAes256GcmSha384 => {
+                DigestAlgorithm::SHA384
+            }
+        }
+    }
+    /// Lengths of the MAC key, write key and implicit IV, AEAD suites don't use a MAC key.
+    pub fn key_lengths(&self) -> (usize, usize, usize) {
+        match self {
+            CipherSuite::EcdheRsaAes128GcmSha256 | CipherSuite::EcdheEcdsaAes128GcmSha256 => {
+                (0, 16, 4)
+            }
+            CipherSuite::EcdheRsaAes256GcmSha384 | CipherSuite::EcdheEcdsaAes256GcmSha384 => {
+                (0, 32, 4)
+            }
         }
     }
 }
@@ -51,7 +179,21 @@ impl TLSVersion {
     pub fn from(e: &[u8]) -> Result<TLSVersion, Error> {
         Ok(match e {
             &[0x03, 0x03] => TLSVersion::TLS1_2,
-            _ => return Err(Error::new("Invalid TLS version.", 5001)),
+            _ => return Err(Error::new("Invalid TLS version.", TlsError::InvalidMessage)),
+        })
+    }
+    /// Clients may send their first records with an older version for compatibility.
+    pub fn from_record(e: &[u8]) -> Result<TLSVersion, Error> {
+        Ok(match e {
+            [0x03, 0x01] => TLSVersion::TLS1_0,
+            [0x03, 0x02] => TLSVersion::TLS1_1,
+            [0x03, 0x03] => TLSVersion::TLS1_2,
+            _ => {
+                return Err(Error::new(
+                    "Unsupported TLS version.",
+                    TlsError::UnsupportedVersion,
+                ))
+            }
         })
     }
     pub fn get_value(&self) -> [u8; 2] {
@@ -63,7 +205,7 @@ impl TLSVersion {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TLSRecord {
     Handshake,
     ChangeCipherSpec,
@@ -73,6 +215,16 @@ pub enum TLSRecord {
 }
 
 impl TLSRecord {
+    pub fn from(value: u8) -> Result<TLSRecord, Error> {
+        Ok(match value {
+            0x14 => TLSRecord::ChangeCipherSpec,
+            0x15 => TLSRecord::Alert,
+            0x16 => TLSRecord::Handshake,
+            0x17 => TLSRecord::Application,
+            0x18 => TLSRecord::Heartbeat,
+            _ => return Err(Error::new("Invalid message.", TlsError::InvalidMessage)),
+        })
+    }
     /// Returns the assigned numerical value equivalent. (ex: 22 - handshake)
     pub fn get_value(&self) -> u8 {
         match self {
@@ -84,3 +236,28 @@ impl TLSRecord {
         }
     }
 }
+
+/// Alerts sent to the client before the connection is closed.
+#[derive(Debug, Clone, Copy)]
+pub enum TLSAlert {
+    CloseNotify,
+    UnexpectedMessage,
+    BadRecordMac,
+    HandshakeFailure,
+    DecodeError,
+    DecryptError,
+}
+
+impl TLSAlert {
+    /// Returns the alert level and description. (ex: [2, 51] - fatal decrypt_error)
+    pub fn get_value(&self) -> [u8; 2] {
+        match self {
+            Self::CloseNotify => [0x01, 0x00],
+            Self::UnexpectedMessage => [0x02, 0x0a],
+            Self::BadRecordMac => [0x02, 0x14],
+            Self::HandshakeFailure => [0x02, 0x28],
+            Self::DecodeError => [0x02, 0x32],
+            Self::DecryptError => [0x02, 0x33],
+        }
+    }
+}
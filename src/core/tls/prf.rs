@@ -0,0 +1,265 @@
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::{Sha256, Sha384};
+
+use super::digest::DigestAlgorithm;
+
+pub const MASTER_SECRET_LENGTH: usize = 48;
+pub const VERIFY_DATA_LENGTH: usize = 12;
+
+/// The TLS 1.2 pseudorandom function (RFC 5246, section 5): P_hash(secret, label + seed).
+pub fn prf(
+    hash: &DigestAlgorithm,
+    secret: &[u8],
+    label: &[u8],
+    seed: &[u8],
+    length: usize,
+) -> Vec<u8> {
+    let seed = [label, seed].concat();
+    match hash {
+        DigestAlgorithm::SHA256 => p_hash(Sha256::new(), secret, &seed, length),
+        DigestAlgorithm::SHA384 => p_hash(Sha384::new(), secret, &seed, length),
+    }
+}
+
+// A(0) = seed, A(i) = HMAC(secret, A(i-1))
+// P_hash = HMAC(secret, A(1) + seed) + HMAC(secret, A(2) + seed) + ...
+fn p_hash<D: Digest>(digest: D, secret: &[u8], seed: &[u8], length: usize) -> Vec<u8> {
+    let mut mac = Hmac::new(digest, secret);
+    let mut output = Vec::with_capacity(length);
+    let mut a = seed.to_vec();
+    while output.len() < length {
+        mac.input(&a);
+        a = mac.result().code().to_vec();
+        mac.reset();
+
+        mac.input(&a);
+        mac.input(seed);
+        output.extend(mac.result().code());
+        mac.reset();
+    }
+    output.truncate(length);
+    output
+}
+
+/// Hash of the handshake messages, used by the extended master secret and the Finished messages.
+pub fn transcript_hash<'a, I>(hash: &DigestAlgorithm, messages: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    match hash {
+        DigestAlgorithm::SHA256 => digest(Sha256::new(), messages),
+        DigestAlgorithm::SHA384 => digest(Sha384::new(), messages),
+    }
+}
+
+fn digest<'a, D: Digest, I>(mut digest: D, messages: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    for message in messages {
+        digest.input(message);
+    }
+    let mut output = vec![0; digest.output_bytes()];
+    digest.result(&mut output);
+    output
+}
+
+pub fn master_secret(
+    hash: &DigestAlgorithm,
+    pre_master_secret: &[u8],
+    client_random: &[u8],
+    server_random: &[u8],
+) -> Vec<u8> {
+    prf(
+        hash,
+        pre_master_secret,
+        b"master secret",
+        &[client_random, server_random].concat(),
+        MASTER_SECRET_LENGTH,
+    )
+}
+
+/// The master secret bound to the whole handshake up to the ClientKeyExchange (RFC 7627).
+pub fn extended_master_secret(
+    hash: &DigestAlgorithm,
+    pre_master_secret: &[u8],
+    session_hash: &[u8],
+) -> Vec<u8> {
+    prf(
+        hash,
+        pre_master_secret,
+        b"extended master secret",
+        session_hash,
+        MASTER_SECRET_LENGTH,
+    )
+}
+
+/// Keys derived from the master secret, AEAD cipher suites use no MAC keys.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBlock {
+    pub client_mac_key: Vec<u8>,
+    pub server_mac_key: Vec<u8>,
+    pub client_write_key: Vec<u8>,
+    pub server_write_key: Vec<u8>,
+    pub client_write_iv: Vec<u8>,
+    pub server_write_iv: Vec<u8>,
+}
+
+/// Splits the key expansion into keys of the given lengths, note the server random comes first in the seed.
+pub fn key_expansion(
+    hash: &DigestAlgorithm,
+    master_secret: &[u8],
+    client_random: &[u8],
+    server_random: &[u8],
+    (mac_length, key_length, iv_length): (usize, usize, usize),
+) -> KeyBlock {
+    let block = prf(
+        hash,
+        master_secret,
+        b"key expansion",
+        &[server_random, client_random].concat(),
+        2 * (mac_length + key_length + iv_length),
+    );
+    let mut rest = &block[..];
+    let mut take = |length: usize| {
+        let (key, next) = rest.split_at(length);
+        rest = next;
+        key.to_vec()
+    };
+    KeyBlock {
+        client_mac_key: take(mac_length),
+        server_mac_key: take(mac_length),
+        client_write_key: take(key_length),
+        server_write_key: take(key_length),
+        client_write_iv: take(iv_length),
+        server_write_iv: take(iv_length),
+    }
+}
+
+/// The verify_data of a Finished message, the label is `client finished` or `server finished`.
+pub fn verify_data(
+    hash: &DigestAlgorithm,
+    master_secret: &[u8],
+    label: &[u8],
+    handshake_hash: &[u8],
+) -> Vec<u8> {
+    prf(
+        hash,
+        master_secret,
+        label,
+        handshake_hash,
+        VERIFY_DATA_LENGTH,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(data: &str) -> Vec<u8> {
+        let data: String = data.split_whitespace().collect();
+        (0..data.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&data[i..(i + 2)], 16).unwrap())
+            .collect()
+    }
+
+    // test vectors published for the TLS 1.2 PRF on the IETF TLS mailing list
+    #[test]
+    fn prf_sha256() {
+        let output = prf(
+            &DigestAlgorithm::SHA256,
+            &hex("9b be 43 6b a9 40 f0 17 b1 76 52 84 9a 71 db 35"),
+            b"test label",
+            &hex("a0 ba 9f 93 6c da 31 18 27 a6 f7 96 ff d5 19 8c"),
+            100,
+        );
+        let expected = hex(
+            "e3 f2 29 ba 72 7b e1 7b 8d 12 26 20 55 7c d4 53 c2 aa b2 1d 07 c3 d4 95 32 9b 52 d4
+             e6 1e db 5a 6b 30 17 91 e9 0d 35 c9 c9 a4 6b 4e 14 ba f9 af 0f a0 22 f7 07 7d ef 17
+             ab fd 37 97 c0 56 4b ab 4f bc 91 66 6e 9d ef 9b 97 fc e3 4f 79 67 89 ba a4 80 82 d1
+             22 ee 42 c5 a7 2e 5a 51 10 ff f7 01 87 34 7b 66",
+        );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn prf_sha384() {
+        let output = prf(
+            &DigestAlgorithm::SHA384,
+            &hex("b8 0b 73 3d 6c ee fc dc 71 56 6e a4 8e 55 67 df"),
+            b"test label",
+            &hex("cd 66 5c f6 a8 44 7d d6 ff 8b 27 55 5e db 74 65"),
+            148,
+        );
+        let expected = hex(
+            "7b 0c 18 e9 ce d4 10 ed 18 04 f2 cf a3 4a 33 6a 1c 14 df fb 49 00 bb 5f d7 94 21 07
+             e8 1c 83 cd e9 ca 0f aa 60 be 9f e3 4f 82 b1 23 3c 91 46 a0 e5 34 cb 40 0f ed 27 00
+             88 4f 9d c2 36 f8 0e dd 8b fa 96 11 44 c9 e8 d7 92 ec a7 22 a7 b3 2f c3 d4 16 d4 73
+             eb c2 c5 fd 4a bf da d0 5d 91 84 25 9b 5b f8 cd 4d 90 fa 0d 31 e2 de c4 79 e4 f1 a2
+             60 66 f2 ee a9 a6 92 36 a3 e5 26 55 c9 e9 ae e6 91 c8 f3 a2 68 54 30 8d 5e aa 3b e8
+             5e 09 90 70 3d 73 e5 6f",
+        );
+        assert_eq!(output, expected);
+    }
+
+    // the remaining expectations are regression values, computed independently with Python's
+    // hmac and hashlib modules from the same inputs
+
+    #[test]
+    fn master_secrets() {
+        let pre_master_secret: Vec<u8> = (0..32).collect();
+        let master_secret = master_secret(
+            &DigestAlgorithm::SHA256,
+            &pre_master_secret,
+            &[1; 32],
+            &[2; 32],
+        );
+        assert_eq!(
+            master_secret,
+            hex("736bbd660032dbca6b2461a1488a84331d57e10b6edb8176b521be9dc3a065fe33d158efe3fd1b46881629e650130686")
+        );
+
+        let session_hash = transcript_hash(&DigestAlgorithm::SHA256, [&b"handshake"[..]]);
+        let extended =
+            extended_master_secret(&DigestAlgorithm::SHA256, &pre_master_secret, &session_hash);
+        assert_eq!(
+            extended,
+            hex("7f4d93c007b440c188b5d4cdee6b98eae475bd534404fb92a96b2acb49fcf61efe152919e234c0390afb077711313fed")
+        );
+    }
+
+    #[test]
+    fn key_block() {
+        let master_secret = hex("736bbd660032dbca6b2461a1488a84331d57e10b6edb8176b521be9dc3a065fe33d158efe3fd1b46881629e650130686");
+        let keys = key_expansion(
+            &DigestAlgorithm::SHA256,
+            &master_secret,
+            &[1; 32],
+            &[2; 32],
+            (0, 16, 4),
+        );
+        let block =
+            hex("f088bbf83f53cebf3f5338d5cf5461b37ee922635f51ba041328b0c758001fbfc6dfd93897de7bf4");
+        assert!(keys.client_mac_key.is_empty() && keys.server_mac_key.is_empty());
+        assert_eq!(keys.client_write_key, block[0..16]);
+        assert_eq!(keys.server_write_key, block[16..32]);
+        assert_eq!(keys.client_write_iv, block[32..36]);
+        assert_eq!(keys.server_write_iv, block[36..40]);
+    }
+
+    #[test]
+    fn finished() {
+        let master_secret = hex("736bbd660032dbca6b2461a1488a84331d57e10b6edb8176b521be9dc3a065fe33d158efe3fd1b46881629e650130686");
+        let handshake_hash = transcript_hash(&DigestAlgorithm::SHA384, [&b"hand"[..], b"shake"]);
+        let verify_data = verify_data(
+            &DigestAlgorithm::SHA384,
+            &master_secret,
+            b"client finished",
+            &handshake_hash,
+        );
+        assert_eq!(verify_data, hex("eb1e8c9a21b344b7263c14c6"));
+    }
+}
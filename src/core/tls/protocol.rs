@@ -1,13 +1,16 @@
-use crate::{join_bytes, Error};
-use crypto::mac::Mac;
+use crate::error::TlsError;
+use crate::Error;
 use rand_core::{OsRng, RngCore};
 
-use super::crypto::{ECCurve, EphemeralPair};
-use super::digest::HmacSha256;
+use super::crypto::EphemeralPair;
+use super::prf;
+use super::signature::SignedData;
 use super::{Certificate, CipherSuite, KeyExchange, TLSExtension, TLSRecord, TLSVersion};
 
 pub struct TLSSession {
-    pub is_encrypted: bool,
+    pub cipher_suite: CipherSuite,
+    /// Whether the client asked for the extended master secret (RFC 7627).
+    pub extended_master_secret: bool,
     pub server_random: [u8; 32],
     pub client_random: Option<Vec<u8>>,
     pub client_public_key: Option<[u8; 32]>,
@@ -25,14 +28,15 @@ pub struct TLSSession {
 }
 
 impl TLSSession {
-    pub fn new() -> TLSSession {
+    pub fn new(cipher_suite: CipherSuite) -> TLSSession {
         let ephemeral_pair = EphemeralPair::new();
 
         let mut server_random = [0u8; 32];
         OsRng.fill_bytes(&mut server_random);
 
         TLSSession {
-            is_encrypted: false,
+            cipher_suite,
+            extended_master_secret: false,
             server_random,
             client_random: None,
             client_public_key: None,
@@ -50,70 +54,76 @@ impl TLSSession {
         }
     }
 
+    /// Derives the master secret and the key block, called once the ClientKeyExchange was added to the handshake messages.
     pub fn calculate_encryption_keys(&mut self) -> Result<(), Error> {
-        if let (Some(client_random), Some(client_public_key)) =
-            (self.client_random.clone(), self.client_public_key.clone())
-        {
-            let shared_key = self.ephemeral_pair.diffie_hellman(&client_public_key);
-
-            let mut seed = b"master secret".to_vec();
-            seed.append(&mut client_random.to_vec());
-            seed.append(&mut self.server_random.to_vec());
-
-            let mut mac = HmacSha256::new(shared_key.as_bytes());
-
-            mac.input(&seed);
-            let a1 = mac.result().code().to_vec();
-            mac.reset();
-
-            mac.input(&a1);
-            let a2 = mac.result().code().to_vec();
-            mac.reset();
-
-            let mut input = a1;
-            input.append(&mut seed.clone());
-            mac.input(&input);
-            let p1 = mac.result().code().to_vec();
-            mac.reset();
-
-            let mut input = a2;
-            input.append(&mut seed.clone());
-            mac.input(&input);
-            let p2 = mac.result().code().to_vec();
-            mac.reset();
-
-            let master_secret = [&p1[..], &p2[0..16]].concat();
-
-            let mut mac = HmacSha256::new(&master_secret);
-            let mut p: Vec<u8> = vec![];
-            let seed = [b"key expansion", &self.server_random[..], &client_random].concat();
-
-            let mut prev_a = seed.clone();
-            while (p.len() / 32) < 4 {
-                mac.input(&prev_a);
-                let a = mac.result().code().to_vec();
-                mac.reset();
-
-                let input = [&a[..], &seed[..]].concat();
-                mac.input(&input);
-
-                let mut new_p = mac.result().code().to_vec();
-                p.append(&mut new_p);
-                mac.reset();
+        let (client_random, client_public_key) =
+            match (&self.client_random, &self.client_public_key) {
+                (Some(client_random), Some(client_public_key)) => {
+                    (client_random, client_public_key)
+                }
+                _ => {
+                    return Err(Error::new(
+                        "Encryption keys cannot be calculated.",
+                        TlsError::KeyCalculation,
+                    ))
+                }
+            };
+        let hash = self.cipher_suite.prf_hash();
+        let pre_master_secret = self.ephemeral_pair.diffie_hellman(client_public_key);
 
-                prev_a = a;
-            }
-            self.master_secret = Some(master_secret);
-            self.client_write_key = Some(p[0..16].to_vec());
-            self.server_write_key = Some(p[16..32].to_vec());
-            self.client_write_iv = Some(p[32..36].to_vec());
-            self.server_write_iv = Some(p[36..40].to_vec());
+        let master_secret = if self.extended_master_secret {
+            let session_hash = self.handshake_hash();
+            prf::extended_master_secret(&hash, pre_master_secret.as_bytes(), &session_hash)
         } else {
-            return Err(Error::new("Encryption keys cannot be calculated.", 5003));
-        }
-
+            prf::master_secret(
+                &hash,
+                pre_master_secret.as_bytes(),
+                client_random,
+                &self.server_random,
+            )
+        };
+        let keys = prf::key_expansion(
+            &hash,
+            &master_secret,
+            client_random,
+            &self.server_random,
+            self.cipher_suite.key_lengths(),
+        );
+
+        self.master_secret = Some(master_secret);
+        self.client_mac_key = Some(keys.client_mac_key);
+        self.server_mac_key = Some(keys.server_mac_key);
+        self.client_write_key = Some(keys.client_write_key);
+        self.server_write_key = Some(keys.server_write_key);
+        self.client_write_iv = Some(keys.client_write_iv);
+        self.server_write_iv = Some(keys.server_write_iv);
         Ok(())
     }
+
+    /// Hash of all handshake messages received and sent so far.
+    fn handshake_hash(&self) -> Vec<u8> {
+        prf::transcript_hash(
+            &self.cipher_suite.prf_hash(),
+            self.handshake_messages
+                .iter()
+                .map(|message| &message.content[..]),
+        )
+    }
+
+    /// Computes the verify_data of a Finished message over all handshake messages received and sent so far.
+    pub fn calculate_verify_data(&self, label: &[u8]) -> Result<Vec<u8>, Error> {
+        let master_secret = self.master_secret.as_ref().ok_or(Error::new(
+            "Encryption keys cannot be calculated.",
+            TlsError::KeyCalculation,
+        ))?;
+
+        Ok(prf::verify_data(
+            &self.cipher_suite.prf_hash(),
+            master_secret,
+            label,
+            &self.handshake_hash(),
+        ))
+    }
 }
 
 #[derive(Debug)]
@@ -132,18 +142,22 @@ impl TLSMessage {
         }
     }
 
-    pub fn get_raw(&self) -> Vec<u8> {
+    pub fn get_raw(&self) -> Result<Vec<u8>, Error> {
         let TLSMessage {
             version,
             record,
             content,
         } = &self;
+        let length = u16::try_from(content.len()).or(Err(Error::new(
+            "The record is too large.",
+            TlsError::RecordTooLarge,
+        )))?;
         let mut raw = vec![record.get_value()];
         raw.extend(version.get_value());
-        raw.extend(u16::to_be_bytes(content.len() as u16));
+        raw.extend(length.to_be_bytes());
         raw.extend(content);
 
-        raw
+        Ok(raw)
     }
 }
 
@@ -151,9 +165,7 @@ impl TLSMessage {
 pub enum HandshakeMessage {
     // Client messages
     ClientHello {
-        version: TLSVersion,
         random: Vec<u8>,
-        session_id: Option<Vec<u8>>,
         cipher_suites: Vec<u16>,
         extensions: Vec<TLSExtension>,
     },
@@ -174,6 +186,7 @@ pub enum HandshakeMessage {
     },
     ServerKeyExchange {
         key_exchange: KeyExchange,
+        signature: SignedData,
     },
     ServerHelloDone,
 
@@ -184,93 +197,75 @@ pub enum HandshakeMessage {
 }
 
 impl HandshakeMessage {
-    pub fn parse(data: &Vec<u8>) -> Result<HandshakeMessage, Error> {
-        let message_body = &data[1..];
-        match data[0] {
+    /// Parses a whole handshake message, lengths which don't match the data are rejected.
+    pub fn parse(data: &[u8]) -> Result<HandshakeMessage, Error> {
+        let invalid = || Error::new("Invalid message.", TlsError::InvalidMessage);
+        // message type (1 byte) + length (3 bytes)
+        let mut fields = Fields(data);
+        let message_type = fields.u8().ok_or_else(invalid)?;
+        let length = fields.u24().ok_or_else(invalid)?;
+        if length != fields.0.len() {
+            return Err(invalid());
+        }
+        let message = match message_type {
             // CLIENT HELLO
             1 => {
-                let session_id_length = message_body[37];
-                let session_id = if session_id_length > 0 {
-                    Some(
-                        message_body[38..(38 + session_id_length as usize)]
-                        .to_vec()
-                           //  .iter()
-                           //  .map(|x| x.to_string())
-                           //  .collect::<String>(),
-                    )
-                } else {
-                    None
-                };
-
-                let mut cursor = 37 + session_id_length as usize;
-                let cipher_suites_length = join_bytes(&message_body[(cursor + 1)..=(cursor + 2)])?;
-
-                let mut cipher_suites: Vec<u16> = Vec::new();
-
-                if cipher_suites_length % 2 != 0 {
-                    return Err(Error::new("Invalid ClientHello message", 5001));
+                // the version is checked, the handshake always uses TLS 1.2
+                TLSVersion::from(fields.take(2).ok_or_else(invalid)?)?;
+                let random = fields.take(32).ok_or_else(invalid)?.to_vec();
+                // sessions are never resumed, the id is only validated
+                let session_id = fields.vector8().ok_or_else(invalid)?;
+                if session_id.len() > 32 {
+                    return Err(invalid());
                 }
-
-                cursor += 2;
-                for (idx, suite) in message_body
-                    [(cursor + 1)..(cursor + 1 + cipher_suites_length as usize)]
-                    .iter()
-                    .step_by(2)
-                    .enumerate()
-                {
-                    if let Some(next_suite) = message_body.get((cursor + 1 + idx * 2) + 1) {
-                        let joined = join_bytes(&[*suite, *next_suite])?;
-                        cipher_suites.push(joined as u16);
-                    }
+                let cipher_suites = fields.vector16().ok_or_else(invalid)?;
+                if cipher_suites.is_empty() || cipher_suites.len() % 2 != 0 {
+                    return Err(invalid());
                 }
-                cursor += 1 + cipher_suites_length as usize;
-
-                // skipping compression methods - not supported in TLS 1.3
-                cursor += 2;
-
-                let extensions_length = join_bytes(&data[(cursor + 1)..=(cursor + 2)])? as usize;
-
-                let mut extensions: Vec<TLSExtension> = Vec::new();
-                let mut i = 0usize;
-                while i < extensions_length {
-                    let c = cursor + 3 + i;
-                    let ext_id = join_bytes(&data[c..=(c + 1)])? as u16;
-                    let ext_length = join_bytes(&data[(c + 2)..=(c + 3)])?;
-                    extensions.push(TLSExtension::new(
-                        ext_id,
-                        // ext_length as u16,
-                        data[(c + 4)..(c + 4 + ext_length as usize)].to_vec(),
-                    ));
-
-                    i += 4 + ext_length as usize;
+                let cipher_suites = cipher_suites
+                    .chunks_exact(2)
+                    .map(|suite| u16::from_be_bytes([suite[0], suite[1]]))
+                    .collect();
+                // compression methods, only null compression is used
+                fields.vector8().ok_or_else(invalid)?;
+
+                // the extensions may be omitted entirely
+                let mut extensions = vec![];
+                if !fields.0.is_empty() {
+                    let mut list = Fields(fields.vector16().ok_or_else(invalid)?);
+                    while !list.0.is_empty() {
+                        let id = list.u16().ok_or_else(invalid)?;
+                        let content = list.vector16().ok_or_else(invalid)?;
+                        extensions.push(TLSExtension::new(id, content.to_vec()));
+                    }
                 }
 
-                Ok(HandshakeMessage::ClientHello {
-                    //   length: join_bytes(&message_body[0..=2])? as usize,
-                    version: TLSVersion::from(&message_body[3..=4])?,
-                    random: message_body[5..=36].to_vec(),
-                    session_id,
+                HandshakeMessage::ClientHello {
+                    random,
                     cipher_suites,
                     extensions,
-                })
+                }
             }
             // CLIENT KEY EXCHANGE
-            16 => {
-                let public_key_length = message_body[3] as usize;
-
-                Ok(HandshakeMessage::ClientKeyExchange {
-                    //   length: join_bytes(&message_body[0..=2])? as usize,
-                    public_key: message_body[4..(4 + public_key_length)].to_vec(),
-                })
-            }
-
+            16 => HandshakeMessage::ClientKeyExchange {
+                public_key: fields.vector8().ok_or_else(invalid)?.to_vec(),
+            },
             // FINISHED
-            20 => Ok(HandshakeMessage::HandshakeFinished {
-                //  length: join_bytes(&message_body[0..=2])? as usize,
-                verify_data: message_body[3..].to_vec(),
-            }),
-            _ => Err(Error::new("Unknown message type.", 5002)),
+            20 => HandshakeMessage::HandshakeFinished {
+                verify_data: fields.take(fields.0.len()).ok_or_else(invalid)?.to_vec(),
+            },
+            _ => {
+                return Err(Error::new(
+                    "Unknown message type.",
+                    TlsError::UnknownMessage,
+                ))
+            }
+        };
+        // trailing data after the last field
+        if !fields.0.is_empty() {
+            return Err(invalid());
         }
+        Ok(message)
     }
 
     pub fn get_raw(&self) -> Result<Vec<u8>, Error> {
@@ -290,7 +285,7 @@ impl HandshakeMessage {
                 if session_id_length > u8::MAX as usize {
                     return Err(Error::new(
                         "Provided session ID is too long. (max 255 bytes)",
-                        5004,
+                        TlsError::InvalidField,
                     ));
                 }
                 let extensions_length = extensions
@@ -363,59 +358,33 @@ impl HandshakeMessage {
                 }
             }
 
-            &Self::ServerKeyExchange { key_exchange } => {
+            &Self::ServerKeyExchange {
+                key_exchange,
+                signature,
+            } => {
                 // ServerKeyExchange type = 0x0c
                 response.push(0x0c);
-                match key_exchange {
-                    KeyExchange::ECDHE { curve, public_key } => {
-                        /*
-                         3 = curve info
-                         1 = public key length field
-                         2 = signature type field
-                         2 = signature length field
-                        */
-
-                        let length = 3 + 1 + public_key.len() + 2 + 2;
-
-                        // Full message length
-                        response.extend(&(length as u32).to_be_bytes()[1..]);
-
-                        // Named curve byte
-                        response.push(0x03);
-                        match curve {
-                            &ECCurve::x25519 => {
-                                // assigned x25519 value
-                                response.extend(&[0x00, 0x1d]);
-                            }
-                        }
-                        if public_key.len() > u8::MAX as usize {
-                            return Err(Error::new(
-                                "Invalid public key. (max length 255 bytes)",
-                                5004,
-                            ));
-                        }
-
-                        // Public key with its length
-                        response.push(public_key.len() as u8);
-                        response.extend(public_key);
-
-                        // if let Some(signature) = signature {
-                        //     // Two byte long signature type field
-                        //     match signature.signature_type {
-                        //         Signature::RSA_SHA256 => {
-                        //             // assigned RSA with SHA256 signature value
-                        //             response.extend(&[0x04, 0x01]);
-                        //         }
-                        //     }
-
-                        //     // Two byte length of the signature
-                        //     response.extend((signature.data.len() as u16).to_be_bytes());
-
-                        //     // The signature itself
-                        //     response.extend(&signature.data);
-                        // }
-                    }
-                }
+                let params = key_exchange.params()?;
+
+                /*
+                 2 = signature scheme field
+                 2 = signature length field
+                */
+                let length = params.len() + 2 + 2 + signature.data.len();
+
+                // Full message length
+                response.extend(&(length as u32).to_be_bytes()[1..]);
+
+                response.extend(&params);
+
+                // Two byte long signature scheme field
+                response.extend(signature.signature_scheme.get_value().to_be_bytes());
+
+                // Two byte length of the signature
+                response.extend((signature.data.len() as u16).to_be_bytes());
+
+                // The signature itself
+                response.extend(&signature.data);
             }
             &Self::ServerHelloDone => {
                 // ServerHelloDone type = 0x0e
@@ -439,43 +408,150 @@ impl HandshakeMessage {
     }
 }
 
-pub fn parse_tls_messages(data: &[u8]) -> Result<Vec<TLSMessage>, Error> {
-    if data.len() == 0 {
-        return Err(Error::new("Invalid message.", 5001));
+// reads the fields of a handshake message front to back, None once the data is too short
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (field, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(field)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|field| field[0])
+    }
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|field| u16::from_be_bytes([field[0], field[1]]))
     }
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|field| u32::from_be_bytes([0, field[0], field[1], field[2]]) as usize)
+    }
+    // a vector with a 1 byte length
+    fn vector8(&mut self) -> Option<&'a [u8]> {
+        let length = self.u8()? as usize;
+        self.take(length)
+    }
+    // a vector with a 2 byte length
+    fn vector16(&mut self) -> Option<&'a [u8]> {
+        let length = self.u16()? as usize;
+        self.take(length)
+    }
+}
 
-    let mut messages: Vec<TLSMessage> = vec![];
-    let mut cursor = 0;
-    while cursor < data.len() - 1 {
-        let record = match data[cursor] {
-            0x14 => TLSRecord::ChangeCipherSpec,
-            0x15 => TLSRecord::Alert,
-            0x16 => TLSRecord::Handshake,
-            0x17 => TLSRecord::Application,
-            0x18 => TLSRecord::Heartbeat,
-            _ => return Err(Error::new("Invalid message", 5001)),
-        };
-        let length = join_bytes(&data[(cursor + 3)..=(cursor + 4)])? as usize;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a ClientHello with a session id, two cipher suites and the extended_master_secret extension
+    fn client_hello(session_id: &[u8], extensions: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend([7; 32]);
+        body.push(session_id.len() as u8);
+        body.extend(session_id);
+        body.extend([0x00, 0x04, 0xc0, 0x2f, 0xc0, 0x2b]);
+        body.extend([0x01, 0x00]);
+        body.extend((extensions.len() as u16).to_be_bytes());
+        body.extend(extensions);
+        handshake(1, &body)
+    }
 
-        let version = match data[cursor + 1..=cursor + 2] {
-            [0x03, 0x01] => TLSVersion::TLS1_0,
-            [0x03, 0x02] => TLSVersion::TLS1_1,
-            [0x03, 0x03] => TLSVersion::TLS1_2,
-            _ => return Err(Error::new("Unsupported TLS version.", 5005)),
-        };
-        let message = TLSMessage {
-            record,
-            version,
-            content: data[(cursor + 5)..(cursor + 5 + length)].to_vec(),
-        };
+    fn handshake(message_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut raw = vec![message_type];
+        raw.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        raw.extend(body);
+        raw
+    }
+
+    fn is_decode_error(result: Result<HandshakeMessage, Error>) -> bool {
+        matches!(
+            result,
+            Err(Error::Tls {
+                kind: TlsError::InvalidMessage,
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn parses_client_hello() {
+        let raw = client_hello(&[1; 32], &[0x00, 0x17, 0x00, 0x00]);
+        match HandshakeMessage::parse(&raw) {
+            Ok(HandshakeMessage::ClientHello {
+                random,
+                cipher_suites,
+                extensions,
+            }) => {
+                assert_eq!(random, [7; 32]);
+                assert_eq!(cipher_suites, [0xc02f, 0xc02b]);
+                assert_eq!(extensions.len(), 1);
+                assert_eq!(extensions[0].id, 0x0017);
+            }
+            _ => panic!("expected a ClientHello"),
+        }
+    }
 
-        // message body
-        cursor += length;
+    #[test]
+    fn rejects_truncated_client_hello() {
+        let raw = client_hello(&[1; 32], &[0x00, 0x17, 0x00, 0x00]);
+        // a ClientHello may end before the extensions
+        let without_extensions = raw.len() - 6;
+        for length in (0..raw.len()).filter(|length| *length != without_extensions) {
+            // the length field is kept so only the missing data makes the message invalid
+            let mut truncated = raw[..length].to_vec();
+            if length > 4 {
+                truncated[1..4].copy_from_slice(&((length - 4) as u32).to_be_bytes()[1..]);
+            }
+            assert!(is_decode_error(HandshakeMessage::parse(&truncated)));
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_client_hello_fields() {
+        // the session id claims 200 bytes but the message ends after it
+        let mut body = vec![0x03, 0x03];
+        body.extend([7; 32]);
+        body.push(200);
+        assert!(is_decode_error(HandshakeMessage::parse(&handshake(
+            1, &body
+        ))));
+
+        // session ids are at most 32 bytes
+        assert!(is_decode_error(HandshakeMessage::parse(&client_hello(
+            &[1; 33],
+            &[]
+        ))));
+
+        // an extension longer than the extension list
+        assert!(is_decode_error(HandshakeMessage::parse(&client_hello(
+            &[],
+            &[0x00, 0x17, 0x00, 0x10]
+        ))));
+
+        // the message length is larger than the message
+        let mut raw = client_hello(&[], &[]);
+        raw[3] += 1;
+        assert!(is_decode_error(HandshakeMessage::parse(&raw)));
+
+        // the compression methods claim more than the rest of the message
+        let mut body = vec![0x03, 0x03];
+        body.extend([7; 32]);
+        body.extend([0x00, 0x00, 0x02, 0xc0, 0x2f, 0xff, 0x00]);
+        assert!(is_decode_error(HandshakeMessage::parse(&handshake(
+            1, &body
+        ))));
+    }
 
-        // meta (record, verison, length)
-        cursor += 5;
+    #[test]
+    fn rejects_truncated_client_key_exchange() {
+        let raw = handshake(16, &[32, 1, 2, 3]);
+        assert!(is_decode_error(HandshakeMessage::parse(&raw)));
 
-        messages.push(message);
+        let raw = handshake(16, &[]);
+        assert!(is_decode_error(HandshakeMessage::parse(&raw)));
     }
-    Ok(messages)
 }
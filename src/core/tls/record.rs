@@ -0,0 +1,382 @@
+use crate::error::TlsError;
+use crate::Error;
+
+use super::crypto::EncryptedMessage;
+use super::protocol::{TLSMessage, TLSSession};
+use super::{TLSRecord, TLSVersion};
+
+/// Largest plaintext fragment of a single record (2^14 bytes).
+pub const MAX_FRAGMENT_LENGTH: usize = 16384;
+/// Largest record accepted from the client, encryption may add up to 2048 bytes (RFC 5246, section 6.2.3).
+pub const MAX_RECORD_LENGTH: usize = MAX_FRAGMENT_LENGTH + 2048;
+
+// record type (1 byte) + version (2 bytes) + length (2 bytes)
+const HEADER_LENGTH: usize = 5;
+const EXPLICIT_NONCE_LENGTH: usize = 8;
+const TAG_LENGTH: usize = 16;
+
+/// Frames the data of a connection into records and protects them with the keys of the session.
+///
+/// Records are sent in plaintext until `change_write_cipher` and read in plaintext until
+/// `change_read_cipher`, afterwards every record is sealed with AES-GCM using the sequence numbers
+/// of the session.
+pub struct RecordLayer {
+    pub session: TLSSession,
+    read_encrypted: bool,
+    write_encrypted: bool,
+    // received data which doesn't form a whole record yet
+    buffer: Vec<u8>,
+}
+
+impl RecordLayer {
+    pub fn new(session: TLSSession) -> RecordLayer {
+        RecordLayer {
+            session,
+            read_encrypted: false,
+            write_encrypted: false,
+            buffer: vec![],
+        }
+    }
+
+    /// Records received after the client's ChangeCipherSpec are encrypted.
+    pub fn change_read_cipher(&mut self) {
+        self.read_encrypted = true;
+        self.session.incoming_encrypted_counter = 0;
+    }
+
+    /// Records sent after the server's ChangeCipherSpec are encrypted.
+    pub fn change_write_cipher(&mut self) {
+        self.write_encrypted = true;
+        self.session.outgoing_encrypted_counter = 0;
+    }
+
+    /// Encodes the data into as many records as needed, each carrying at most 16 KiB of it.
+    pub fn seal(&mut self, record: TLSRecord, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut raw = vec![];
+        // empty application data still has to be sent as a record
+        let fragments: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(MAX_FRAGMENT_LENGTH).collect()
+        };
+        for fragment in fragments {
+            let content = if self.write_encrypted {
+                self.encrypt(&record, fragment)?
+            } else {
+                fragment.to_vec()
+            };
+            raw.extend(TLSMessage::new(record.clone(), TLSVersion::TLS1_2, content).get_raw()?);
+        }
+        Ok(raw)
+    }
+
+    /// Buffers data read from the stream, a record may arrive over multiple reads.
+    pub fn receive(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Whether part of a record was received.
+    pub fn has_partial_record(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Takes the next whole record out of the buffer and decrypts it, None until it was fully received.
+    pub fn open(&mut self) -> Result<Option<TLSMessage>, Error> {
+        if self.buffer.len() < HEADER_LENGTH {
+            return Ok(None);
+        }
+        let record = TLSRecord::from(self.buffer[0])?;
+        let version = TLSVersion::from_record(&self.buffer[1..3])?;
+        let length = u16::from_be_bytes([self.buffer[3], self.buffer[4]]) as usize;
+        let max_length = if self.read_encrypted {
+            MAX_RECORD_LENGTH
+        } else {
+            MAX_FRAGMENT_LENGTH
+        };
+        if length > max_length {
+            return Err(Error::new(
+                "The record is too large.",
+                TlsError::RecordTooLarge,
+            ));
+        }
+        if self.buffer.len() < HEADER_LENGTH + length {
+            return Ok(None);
+        }
+
+        let content: Vec<u8> = self
+            .buffer
+            .drain(..(HEADER_LENGTH + length))
+            .skip(HEADER_LENGTH)
+            .collect();
+        let content = if self.read_encrypted {
+            let plaintext = self.decrypt(&record, &version, &content)?;
+            if plaintext.len() > MAX_FRAGMENT_LENGTH {
+                return Err(Error::new(
+                    "The record is too large.",
+                    TlsError::RecordTooLarge,
+                ));
+            }
+            plaintext
+        } else {
+            content
+        };
+        Ok(Some(TLSMessage::new(record, version, content)))
+    }
+
+    // the sequence number must not wrap, the connection has to be closed before
+    fn next_sequence_number(counter: &mut u64) -> Result<[u8; 8], Error> {
+        let sequence_number = *counter;
+        *counter = counter.checked_add(1).ok_or(Error::new(
+            "The sequence number is exhausted.",
+            TlsError::Crypto,
+        ))?;
+        Ok(sequence_number.to_be_bytes())
+    }
+
+    /// Encrypts a fragment using the server write key and the outgoing sequence number.
+    fn encrypt(&mut self, record: &TLSRecord, fragment: &[u8]) -> Result<Vec<u8>, Error> {
+        let (write_key, write_iv) = match (
+            &self.session.server_write_key,
+            &self.session.server_write_iv,
+        ) {
+            (Some(write_key), Some(write_iv)) => (write_key, write_iv),
+            _ => {
+                return Err(Error::new(
+                    "Encryption keys cannot be calculated.",
+                    TlsError::KeyCalculation,
+                ))
+            }
+        };
+        let sequence_number =
+            RecordLayer::next_sequence_number(&mut self.session.outgoing_encrypted_counter)?;
+
+        // the sequence number doubles as the explicit nonce, it is unique for every record
+        let nonce = [&write_iv[..], &sequence_number[..]].concat();
+        let aad = additional_data(
+            &sequence_number,
+            record,
+            &TLSVersion::TLS1_2,
+            fragment.len(),
+        );
+
+        let encrypted = EncryptedMessage::encrypt(fragment, &nonce, write_key, &aad);
+        Ok([&sequence_number[..], &encrypted[..]].concat())
+    }
+
+    /// Decrypts a record using the client write key and the incoming sequence number.
+    fn decrypt(
+        &mut self,
+        record: &TLSRecord,
+        version: &TLSVersion,
+        content: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let (write_key, write_iv) = match (
+            &self.session.client_write_key,
+            &self.session.client_write_iv,
+        ) {
+            (Some(write_key), Some(write_iv)) => (write_key, write_iv),
+            _ => {
+                return Err(Error::new(
+                    "Encryption keys cannot be calculated.",
+                    TlsError::KeyCalculation,
+                ))
+            }
+        };
+        if content.len() < EXPLICIT_NONCE_LENGTH + TAG_LENGTH {
+            return Err(Error::new("Invalid message.", TlsError::InvalidMessage));
+        }
+        let sequence_number =
+            RecordLayer::next_sequence_number(&mut self.session.incoming_encrypted_counter)?;
+
+        // nonce = implicit IV (4 bytes) + explicit nonce (8 bytes)
+        let (explicit_nonce, encrypted) = content.split_at(EXPLICIT_NONCE_LENGTH);
+        let nonce = [&write_iv[..], explicit_nonce].concat();
+        let plaintext_length = encrypted.len() - TAG_LENGTH;
+        let aad = additional_data(&sequence_number, record, version, plaintext_length);
+
+        EncryptedMessage::decrypt(encrypted, &nonce, write_key, &aad)
+    }
+}
+
+// sequence number (8 bytes) + record type (1 byte) + version (2 bytes) + plaintext length (2 bytes)
+fn additional_data(
+    sequence_number: &[u8; 8],
+    record: &TLSRecord,
+    version: &TLSVersion,
+    length: usize,
+) -> Vec<u8> {
+    [
+        &sequence_number[..],
+        &[record.get_value()],
+        &version.get_value(),
+        &(length as u16).to_be_bytes(),
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tls::CipherSuite;
+    use crate::error::ErrorKind;
+
+    // both directions use the same keys, so the layer can open what it sealed
+    fn encrypted_layer() -> RecordLayer {
+        let mut session = TLSSession::new(CipherSuite::EcdheRsaAes128GcmSha256);
+        session.client_write_key = Some(vec![7; 16]);
+        session.server_write_key = Some(vec![7; 16]);
+        session.client_write_iv = Some(vec![9; 4]);
+        session.server_write_iv = Some(vec![9; 4]);
+        let mut layer = RecordLayer::new(session);
+        layer.change_read_cipher();
+        layer.change_write_cipher();
+        layer
+    }
+
+    fn plaintext_layer() -> RecordLayer {
+        RecordLayer::new(TLSSession::new(CipherSuite::EcdheRsaAes128GcmSha256))
+    }
+
+    fn error_kind(result: Result<Option<TLSMessage>, Error>) -> Option<ErrorKind> {
+        result.err().map(|e| e.kind())
+    }
+
+    // lengths of the records in the data
+    fn record_lengths(mut raw: &[u8]) -> Vec<usize> {
+        let mut lengths = vec![];
+        while raw.len() >= HEADER_LENGTH {
+            let length = u16::from_be_bytes([raw[3], raw[4]]) as usize;
+            lengths.push(length);
+            raw = &raw[HEADER_LENGTH + length..];
+        }
+        lengths
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        for mut layer in [plaintext_layer(), encrypted_layer()] {
+            let raw = layer.seal(TLSRecord::Application, b"Hello").unwrap();
+            assert_eq!(raw[..3], [0x17, 0x03, 0x03]);
+            layer.receive(&raw);
+            let message = layer.open().unwrap().unwrap();
+            assert!(matches!(message.record, TLSRecord::Application));
+            assert_eq!(message.content, b"Hello");
+            assert!(!layer.has_partial_record());
+            assert!(layer.open().unwrap().is_none());
+        }
+
+        // the plaintext is not visible in encrypted records
+        let raw = encrypted_layer()
+            .seal(TLSRecord::Application, b"Hello")
+            .unwrap();
+        assert_eq!(
+            record_lengths(&raw),
+            vec![EXPLICIT_NONCE_LENGTH + 5 + TAG_LENGTH]
+        );
+        assert!(!raw.windows(5).any(|window| window == b"Hello"));
+    }
+
+    #[test]
+    fn fragment_large_payloads() {
+        let data: Vec<u8> = (0..40000).map(|i| i as u8).collect();
+        let mut layer = encrypted_layer();
+        let raw = layer.seal(TLSRecord::Application, &data).unwrap();
+        let overhead = EXPLICIT_NONCE_LENGTH + TAG_LENGTH;
+        assert_eq!(
+            record_lengths(&raw),
+            vec![
+                MAX_FRAGMENT_LENGTH + overhead,
+                MAX_FRAGMENT_LENGTH + overhead,
+                40000 - 2 * MAX_FRAGMENT_LENGTH + overhead
+            ]
+        );
+
+        layer.receive(&raw);
+        let mut received = vec![];
+        while let Some(message) = layer.open().unwrap() {
+            received.extend(message.content);
+        }
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn reject_oversized_records() {
+        let length = (MAX_FRAGMENT_LENGTH + 1) as u16;
+        let mut layer = plaintext_layer();
+        layer.receive(&[&[0x17, 0x03, 0x03][..], &length.to_be_bytes()].concat());
+        assert_eq!(
+            error_kind(layer.open()),
+            Some(ErrorKind::Tls(TlsError::RecordTooLarge))
+        );
+
+        // encrypted records may be larger, but not beyond the expansion limit
+        let mut layer = encrypted_layer();
+        let length = MAX_RECORD_LENGTH as u16;
+        layer.receive(&[&[0x17, 0x03, 0x03][..], &length.to_be_bytes()].concat());
+        assert!(layer.open().unwrap().is_none());
+        let mut layer = encrypted_layer();
+        let length = (MAX_RECORD_LENGTH + 1) as u16;
+        layer.receive(&[&[0x17, 0x03, 0x03][..], &length.to_be_bytes()].concat());
+        assert_eq!(
+            error_kind(layer.open()),
+            Some(ErrorKind::Tls(TlsError::RecordTooLarge))
+        );
+    }
+
+    #[test]
+    fn reject_reordered_and_tampered_records() {
+        let mut layer = encrypted_layer();
+        let first = layer.seal(TLSRecord::Application, b"first").unwrap();
+        let second = layer.seal(TLSRecord::Application, b"second").unwrap();
+
+        // the sequence number in the additional data doesn't match
+        let mut reader = encrypted_layer();
+        reader.receive(&second);
+        assert_eq!(
+            error_kind(reader.open()),
+            Some(ErrorKind::Tls(TlsError::Crypto))
+        );
+
+        let mut reader = encrypted_layer();
+        let mut tampered = first.clone();
+        let last = tampered.len() - TAG_LENGTH - 1;
+        tampered[last] ^= 1;
+        reader.receive(&tampered);
+        assert_eq!(
+            error_kind(reader.open()),
+            Some(ErrorKind::Tls(TlsError::Crypto))
+        );
+
+        // the record type is authenticated as well
+        let mut reader = encrypted_layer();
+        let mut retyped = first.clone();
+        retyped[0] = 0x16;
+        reader.receive(&retyped);
+        assert_eq!(
+            error_kind(reader.open()),
+            Some(ErrorKind::Tls(TlsError::Crypto))
+        );
+
+        let mut reader = encrypted_layer();
+        reader.receive(&[first, second].concat());
+        assert_eq!(reader.open().unwrap().unwrap().content, b"first");
+        assert_eq!(reader.open().unwrap().unwrap().content, b"second");
+    }
+
+    #[test]
+    fn buffer_partial_records() {
+        let mut layer = encrypted_layer();
+        let raw = layer.seal(TLSRecord::Application, b"split record").unwrap();
+
+        // the header and the body both arrive in parts
+        for split in [3, HEADER_LENGTH + 10] {
+            let mut reader = encrypted_layer();
+            reader.receive(&raw[..split]);
+            assert!(reader.open().unwrap().is_none());
+            assert!(reader.has_partial_record());
+            reader.receive(&raw[split..]);
+            assert_eq!(reader.open().unwrap().unwrap().content, b"split record");
+            assert!(!reader.has_partial_record());
+        }
+    }
+}
@@ -1,7 +1,15 @@
+use std::sync::Arc;
+
+use crate::error::TlsError;
 use crate::Error;
+use ring::signature::{EcdsaKeyPair, RsaEncoding, RsaKeyPair};
 use ring::{rand, signature};
 
-use super::digest::DigestAlgorithm;
+use super::certificate::{EcdsaCurve, PrivateKey};
+use super::TLSExtension;
+
+// signature_algorithms extension id
+const SIGNATURE_ALGORITHMS: u16 = 0x000d;
 
 #[derive(Debug, Clone)]
 pub struct SignedData {
@@ -9,32 +17,158 @@ pub struct SignedData {
     pub data: Vec<u8>,
 }
 
-#[derive(Debug, Clone)]
+/// Signature schemes the server can sign the handshake with.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Signature {
-    RSA_SHA256,
+    RsaPkcs1Sha256,
+    RsaPkcs1Sha384,
+    RsaPkcs1Sha512,
+    RsaPssSha256,
+    RsaPssSha384,
+    RsaPssSha512,
+    EcdsaSha256,
+    EcdsaSha384,
+}
+
+impl Signature {
+    /// Returns the assigned SignatureScheme value. (ex: 0x0401 - rsa_pkcs1_sha256)
+    pub fn get_value(&self) -> u16 {
+        match self {
+            Signature::RsaPkcs1Sha256 => 0x0401,
+            Signature::RsaPkcs1Sha384 => 0x0501,
+            Signature::RsaPkcs1Sha512 => 0x0601,
+            Signature::RsaPssSha256 => 0x0804,
+            Signature::RsaPssSha384 => 0x0805,
+            Signature::RsaPssSha512 => 0x0806,
+            Signature::EcdsaSha256 => 0x0403,
+            Signature::EcdsaSha384 => 0x0503,
+        }
+    }
+    pub fn from(value: u16) -> Option<Signature> {
+        Some(match value {
+            0x0401 => Signature::RsaPkcs1Sha256,
+            0x0501 => Signature::RsaPkcs1Sha384,
+            0x0601 => Signature::RsaPkcs1Sha512,
+            0x0804 => Signature::RsaPssSha256,
+            0x0805 => Signature::RsaPssSha384,
+            0x0806 => Signature::RsaPssSha512,
+            0x0403 => Signature::EcdsaSha256,
+            0x0503 => Signature::EcdsaSha384,
+            _ => return None,
+        })
+    }
+}
+
+/// Reads the schemes offered in the signature_algorithms extension of the ClientHello, most preferred first.
+///
+/// Clients which don't send the extension get `rsa_pkcs1_sha256` instead of the SHA-1 default of RFC 5246.
+pub fn offered_schemes(extensions: &[TLSExtension]) -> Vec<Signature> {
+    let extension = match extensions.iter().find(|ext| ext.id == SIGNATURE_ALGORITHMS) {
+        Some(extension) => extension,
+        None => return vec![Signature::RsaPkcs1Sha256, Signature::EcdsaSha256],
+    };
+    // 2 bytes list length followed by 2 bytes per scheme
+    extension
+        .content
+        .get(2..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .filter_map(|scheme| Signature::from(u16::from_be_bytes([scheme[0], scheme[1]])))
+        .collect()
+}
+
+/// Signs the data with the first offered scheme the private key supports.
+pub fn sign(
+    private_key: &PrivateKey,
+    offered: &[Signature],
+    data: &[u8],
+) -> Result<SignedData, Error> {
+    match private_key {
+        PrivateKey::Rsa(key_pair) => {
+            let scheme = offered
+                .iter()
+                .find(|scheme| RSASignature::supports(scheme))
+                .ok_or(Error::new(
+                    "No supported signature scheme was offered.",
+                    TlsError::HandshakeFailure,
+                ))?;
+            RSASignature::new(key_pair.clone()).sign(*scheme, data)
+        }
+        PrivateKey::Ecdsa(key_pair, curve) => {
+            // the curve of the key decides the hash
+            let scheme = match curve {
+                EcdsaCurve::P256 => Signature::EcdsaSha256,
+                EcdsaCurve::P384 => Signature::EcdsaSha384,
+            };
+            if !offered.contains(&scheme) {
+                return Err(Error::new(
+                    "No supported signature scheme was offered.",
+                    TlsError::HandshakeFailure,
+                ));
+            }
+            ECDSASignature::new(key_pair.clone()).sign(scheme, data)
+        }
+    }
 }
 
 pub struct RSASignature {
-    encryption_key: Vec<u8>,
+    key_pair: Arc<RsaKeyPair>,
 }
 impl RSASignature {
-    pub fn new(encryption_key: Vec<u8>) -> RSASignature {
-        RSASignature { encryption_key }
+    pub fn new(key_pair: Arc<RsaKeyPair>) -> RSASignature {
+        RSASignature { key_pair }
     }
-    pub fn sign(&self, digest: DigestAlgorithm, data: &[u8]) -> Result<SignedData, Error> {
-        let (padding, signature_scheme) = match digest {
-            DigestAlgorithm::SHA256 => (&signature::RSA_PKCS1_SHA256, Signature::RSA_SHA256),
-        };
-        let key_pair = signature::RsaKeyPair::from_der(&self.encryption_key).unwrap();
+    fn supports(scheme: &Signature) -> bool {
+        RSASignature::padding(scheme).is_some()
+    }
+    fn padding(scheme: &Signature) -> Option<&'static dyn RsaEncoding> {
+        Some(match scheme {
+            Signature::RsaPkcs1Sha256 => &signature::RSA_PKCS1_SHA256,
+            Signature::RsaPkcs1Sha384 => &signature::RSA_PKCS1_SHA384,
+            Signature::RsaPkcs1Sha512 => &signature::RSA_PKCS1_SHA512,
+            Signature::RsaPssSha256 => &signature::RSA_PSS_SHA256,
+            Signature::RsaPssSha384 => &signature::RSA_PSS_SHA384,
+            Signature::RsaPssSha512 => &signature::RSA_PSS_SHA512,
+            _ => return None,
+        })
+    }
+    pub fn sign(&self, signature_scheme: Signature, data: &[u8]) -> Result<SignedData, Error> {
+        let padding = RSASignature::padding(&signature_scheme).ok_or(Error::new(
+            "Unsupported signature scheme.",
+            TlsError::Crypto,
+        ))?;
 
-        // Sign the message, using PKCS#1 v1.5 padding and the
-        // SHA256 digest algorithm.
         let rng = rand::SystemRandom::new();
-        let mut signature = vec![0; key_pair.public_modulus_len()];
-        key_pair.sign(padding, &rng, &data, &mut signature).unwrap();
+        let mut signature = vec![0; self.key_pair.public_modulus_len()];
+        self.key_pair
+            .sign(padding, &rng, data, &mut signature)
+            .or(Err(Error::new(
+                "Failed to sign the data.",
+                TlsError::Crypto,
+            )))?;
         Ok(SignedData {
             signature_scheme,
             data: signature,
         })
     }
 }
+
+pub struct ECDSASignature {
+    key_pair: Arc<EcdsaKeyPair>,
+}
+impl ECDSASignature {
+    pub fn new(key_pair: Arc<EcdsaKeyPair>) -> ECDSASignature {
+        ECDSASignature { key_pair }
+    }
+    pub fn sign(&self, signature_scheme: Signature, data: &[u8]) -> Result<SignedData, Error> {
+        let rng = rand::SystemRandom::new();
+        let signature = self.key_pair.sign(&rng, data).or(Err(Error::new(
+            "Failed to sign the data.",
+            TlsError::Crypto,
+        )))?;
+        Ok(SignedData {
+            signature_scheme,
+            data: signature.as_ref().to_vec(),
+        })
+    }
+}
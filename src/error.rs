@@ -0,0 +1,228 @@
+use std::fmt;
+
+/// The underlying failure of an `Error` (ex.: an I/O or parse error).
+pub type Source = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Errors returned by cree, grouped by the part of the server they come from.
+///
+/// Every variant carries the kind of the failure, which callers can match on
+/// (ex.: `Error::Http { kind: HttpError::HeadTooLarge, .. }`). The kinds map to the
+/// numeric codes listed in `spec/error_codes.md`, returned by `Error::code`.
+#[derive(Debug)]
+pub enum Error {
+    /// Streams, files and other system resources (1000).
+    System {
+        kind: SystemError,
+        msg: String,
+        source: Option<Source>,
+    },
+    /// Invalid requests and misuse of responses (2000).
+    Http {
+        kind: HttpError,
+        msg: String,
+        source: Option<Source>,
+    },
+    /// Connections and the listener (4000).
+    Tcp {
+        kind: TcpError,
+        msg: String,
+        source: Option<Source>,
+    },
+    /// TLS handshakes, records and keys (5000).
+    Tls {
+        kind: TlsError,
+        msg: String,
+        source: Option<Source>,
+    },
+    /// WebSocket handshakes and frames (6000).
+    WebSocket {
+        kind: WebSocketError,
+        msg: String,
+        source: Option<Source>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemError {
+    InvalidFileName = 1001,
+    StreamRead = 1002,
+    StreamWrite = 1003,
+    StreamShutdown = 1004,
+    FileRead = 1005,
+    StreamFlush = 1006,
+    InvalidInput = 1007,
+    FileWrite = 1008,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpError {
+    InvalidRequest = 2001,
+    InvalidMethod = 2002,
+    InvalidStatus = 2003,
+    TooManyRequests = 2004,
+    Stalling = 2005,
+    HeadTooLarge = 2006,
+    ResponseSent = 2007,
+    ResponsesPending = 2008,
+    Timeout = 2009,
+    BodyTooLarge = 2010,
+    InvalidCookie = 2011,
+    InvalidHeader = 2012,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpError {
+    RemoteAddress = 4001,
+    TooManyMessages = 4002,
+    Stalling = 4003,
+    ShutdownTimeout = 4004,
+    ListenerClosed = 4005,
+    Bind = 4006,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsError {
+    InvalidMessage = 5001,
+    UnknownMessage = 5002,
+    KeyCalculation = 5003,
+    InvalidField = 5004,
+    UnsupportedVersion = 5005,
+    Crypto = 5006,
+    HandshakeFailure = 5007,
+    InvalidCertificate = 5008,
+    InvalidPrivateKey = 5009,
+    KeyMismatch = 5010,
+    RecordTooLarge = 5011,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebSocketError {
+    InvalidFrame = 6001,
+    MessageTooLarge = 6002,
+    InvalidHandshake = 6003,
+    Closing = 6004,
+}
+
+/// The kind of any `Error`, accepted by `Error::new`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    System(SystemError),
+    Http(HttpError),
+    Tcp(TcpError),
+    Tls(TlsError),
+    WebSocket(WebSocketError),
+}
+
+impl ErrorKind {
+    /// The numeric code of `spec/error_codes.md`.
+    pub fn code(&self) -> u32 {
+        match self {
+            ErrorKind::System(kind) => *kind as u32,
+            ErrorKind::Http(kind) => *kind as u32,
+            ErrorKind::Tcp(kind) => *kind as u32,
+            ErrorKind::Tls(kind) => *kind as u32,
+            ErrorKind::WebSocket(kind) => *kind as u32,
+        }
+    }
+}
+
+impl From<SystemError> for ErrorKind {
+    fn from(kind: SystemError) -> ErrorKind {
+        ErrorKind::System(kind)
+    }
+}
+impl From<HttpError> for ErrorKind {
+    fn from(kind: HttpError) -> ErrorKind {
+        ErrorKind::Http(kind)
+    }
+}
+impl From<TcpError> for ErrorKind {
+    fn from(kind: TcpError) -> ErrorKind {
+        ErrorKind::Tcp(kind)
+    }
+}
+impl From<TlsError> for ErrorKind {
+    fn from(kind: TlsError) -> ErrorKind {
+        ErrorKind::Tls(kind)
+    }
+}
+impl From<WebSocketError> for ErrorKind {
+    fn from(kind: WebSocketError) -> ErrorKind {
+        ErrorKind::WebSocket(kind)
+    }
+}
+
+impl Error {
+    /// Creates an error of the variant the kind belongs to.
+    pub fn new<K: Into<ErrorKind>>(msg: &str, kind: K) -> Error {
+        let msg = msg.to_owned();
+        let source = None;
+        match kind.into() {
+            ErrorKind::System(kind) => Error::System { kind, msg, source },
+            ErrorKind::Http(kind) => Error::Http { kind, msg, source },
+            ErrorKind::Tcp(kind) => Error::Tcp { kind, msg, source },
+            ErrorKind::Tls(kind) => Error::Tls { kind, msg, source },
+            ErrorKind::WebSocket(kind) => Error::WebSocket { kind, msg, source },
+        }
+    }
+
+    /// Attaches the failure which caused this error, returned by `std::error::Error::source`.
+    pub fn with_source<E>(mut self, error: E) -> Error
+    where
+        E: Into<Source>,
+    {
+        match &mut self {
+            Error::System { source, .. }
+            | Error::Http { source, .. }
+            | Error::Tcp { source, .. }
+            | Error::Tls { source, .. }
+            | Error::WebSocket { source, .. } => *source = Some(error.into()),
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::System { kind, .. } => ErrorKind::System(*kind),
+            Error::Http { kind, .. } => ErrorKind::Http(*kind),
+            Error::Tcp { kind, .. } => ErrorKind::Tcp(*kind),
+            Error::Tls { kind, .. } => ErrorKind::Tls(*kind),
+            Error::WebSocket { kind, .. } => ErrorKind::WebSocket(*kind),
+        }
+    }
+
+    /// The numeric code of the kind, for logs.
+    pub fn code(&self) -> u32 {
+        self.kind().code()
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::System { msg, .. }
+            | Error::Http { msg, .. }
+            | Error::Tcp { msg, .. }
+            | Error::Tls { msg, .. }
+            | Error::WebSocket { msg, .. } => msg,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::System { source, .. }
+            | Error::Http { source, .. }
+            | Error::Tcp { source, .. }
+            | Error::Tls { source, .. }
+            | Error::WebSocket { source, .. } => source
+                .as_ref()
+                .map(|source| source.as_ref() as &(dyn std::error::Error + 'static)),
+        }
+    }
+}
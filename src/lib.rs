@@ -10,6 +10,15 @@ use tokio::net::TcpStream;
 
 pub mod api;
 mod core;
+pub mod error;
+pub mod middleware;
+pub mod router;
+pub mod session;
+pub mod static_files;
+pub mod websocket;
+
+pub use error::Error;
+use error::SystemError;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Headers {
@@ -39,35 +48,22 @@ impl CreeOptions {
     }
 }
 
-#[derive(Debug)]
-pub struct Error {
-    pub msg: String,
-    pub code: u32,
-}
-
-impl Error {
-    pub fn new(msg: &str, code: u32) -> Error {
-        Error {
-            msg: msg.to_owned(),
-            code,
-        }
-    }
-}
-
 pub const M_BYTE: usize = 1048576;
 
 pub fn join_bytes(bytes: &[u8]) -> Result<u64, Error> {
     if bytes.len() > 8 {
-        return Err(Error::new("Invalid input. (max length is 8)", 1007));
+        return Err(Error::new(
+            "Invalid input. (max length is 8)",
+            SystemError::InvalidInput,
+        ));
     }
     let mut full_bytes = vec![0u8; 8 - bytes.len()];
     full_bytes.extend(bytes);
 
     let mut bytes = [0u8; 8];
-    full_bytes
-        .reader()
-        .read(&mut bytes)
-        .or(Err(Error::new("Failed to read the bytes.", 1002)))?;
+    full_bytes.reader().read(&mut bytes).map_err(|e| {
+        Error::new("Failed to read the bytes.", SystemError::StreamRead).with_source(e)
+    })?;
 
     Ok(u64::from_be_bytes(bytes))
 }
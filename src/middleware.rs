@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use futures::Future;
+use libflate::{deflate::Encoder as DfEncoder, gzip::Encoder as GzEncoder};
+
+use crate::core::http::{
+    protocol::{Request, Response},
+    Encoding,
+};
+use crate::router::{HandlerFuture, Router};
+
+/// A step of the request processing chain. A middleware can modify the request and the response
+/// before passing them on by calling `next.run(req, res)`, or end the chain by sending the response itself.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, req: Request, res: Response, next: Next) -> HandlerFuture;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Request, Response, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn handle(&self, req: Request, res: Response, next: Next) -> HandlerFuture {
+        Box::pin((self)(req, res, next))
+    }
+}
+
+/// The remaining part of the middleware chain, the last step dispatches the request to the matching route.
+pub struct Next {
+    pub(crate) router: Arc<Router>,
+    pub(crate) index: usize,
+}
+
+impl Next {
+    pub async fn run(self, req: Request, res: Response) {
+        let Next { router, index } = self;
+        match router.middleware.get(index).cloned() {
+            Some(middleware) => {
+                let next = Next {
+                    router,
+                    index: index + 1,
+                };
+                middleware.handle(req, res, next).await
+            }
+            None => router.run_routes(req, res).await,
+        }
+    }
+}
+
+/// Compresses response bodies with gzip or deflate based on the `Accept-Encoding` request header.
+pub async fn compression(req: Request, mut res: Response, next: Next) {
+    res.on_send(compress);
+    next.run(req, res).await
+}
+
+/// Prints the method, path and status of each response once it is sent.
+pub async fn logger(req: Request, mut res: Response, next: Next) {
+    res.on_send(|res, body| {
+        let req = res.request();
+        println!(
+            "{} {} {:?} ({}ms)",
+            req.method
+                .to_string()
+                .unwrap_or_else(|| String::from("UNKNOWN")),
+            req.uri,
+            res.get_status(),
+            req.duration().num_milliseconds()
+        );
+        body
+    });
+    next.run(req, res).await
+}
+
+fn compress(res: &mut Response, body: Vec<u8>) -> Vec<u8> {
+    // chunks are sent as they are
+    if res.is_chunked() {
+        return body;
+    }
+    // these responses have no content to encode
+    let code = res.get_status().code();
+    if body.is_empty() || code < 200 || code == 204 || code == 304 {
+        return body;
+    }
+    // the encoding of the body depends on the request's Accept-Encoding
+    let varies = res.get_header("vary").is_some_and(|vary| {
+        vary.split(',')
+            .any(|i| i.trim() == "*" || i.trim().eq_ignore_ascii_case("accept-encoding"))
+    });
+    if !varies {
+        let _ = res.append_header("Vary", "Accept-Encoding");
+    }
+
+    let accept_encoding = match res.request().headers.get_joined("accept-encoding") {
+        Some(accept_encoding) => accept_encoding,
+        None => return body,
+    };
+    let accept_encoding: Vec<String> = accept_encoding
+        .split(",")
+        .map(|i| i.trim().to_lowercase())
+        .collect();
+    let has_gzip = accept_encoding.contains(&String::from("gzip"));
+    let has_deflate = accept_encoding.contains(&String::from("deflate"));
+
+    let mut content_encoding: Option<Encoding> = None;
+
+    // Gzip usually increases file size in files with less than 1000 bytes
+    if body.len() > 1000 && has_gzip {
+        content_encoding = Some(Encoding::Gzip);
+    } else if has_deflate {
+        content_encoding = Some(Encoding::Deflate);
+    };
+
+    if let Some(content_encoding) = content_encoding {
+        let encoded = match content_encoding {
+            Encoding::Gzip => gzip(&body).map(|data| (data, "gzip")),
+            Encoding::Deflate => deflate(&body).map(|data| (data, "deflate")),
+        };
+        // the body is sent uncompressed if compression fails
+        if let Ok((encoded_data, encoding_name)) = encoded {
+            let _ = res.set_header("Content-Encoding", encoding_name);
+            return encoded_data;
+        }
+    }
+    body
+}
+
+fn gzip(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new())?;
+    std::io::copy(&mut &body[..], &mut encoder)?;
+    encoder.finish().into_result()
+}
+
+fn deflate(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = DfEncoder::new(Vec::new());
+    std::io::copy(&mut &body[..], &mut encoder)?;
+    encoder.finish().into_result()
+}
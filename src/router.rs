@@ -0,0 +1,512 @@
+use std::{collections::HashMap, pin::Pin, sync::Arc};
+
+use futures::Future;
+
+use crate::core::http::{
+    codes::HTTPStatus,
+    decode_uri_component,
+    protocol::{Method, Request, Response},
+};
+use crate::middleware::{Middleware, Next};
+
+// methods which can be routed, in the order they are listed in the Allow header
+const ALLOWED_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+    Method::OPTIONS,
+    Method::CONNECT,
+    Method::TRACE,
+];
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// An async request handler, implemented for any `Fn(Request, Response) -> impl Future<Output = ()>`.
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, req: Request, res: Response) -> HandlerFuture;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn call(&self, req: Request, res: Response) -> HandlerFuture {
+        Box::pin((self)(req, res))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard,
+}
+
+struct Route {
+    // None matches any method
+    method: Option<Method>,
+    segments: Vec<Segment>,
+    handler: Arc<dyn Handler>,
+}
+
+impl Route {
+    fn new(method: Option<Method>, path: &str, handler: Arc<dyn Handler>) -> Route {
+        let segments = split_path(path)
+            .map(|segment| {
+                if segment == "*" {
+                    Segment::Wildcard
+                } else if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_owned())
+                } else {
+                    Segment::Static(segment.to_owned())
+                }
+            })
+            .collect();
+        Route {
+            method,
+            segments,
+            handler,
+        }
+    }
+
+    fn matches_method(&self, method: &Method) -> bool {
+        match &self.method {
+            None => true,
+            // GET routes also respond to HEAD, the body is stripped when sending
+            Some(Method::GET) => method == &Method::GET || method == &Method::HEAD,
+            Some(route_method) => route_method == method,
+        }
+    }
+
+    // returns the captured parameters if the path matches this route
+    fn matches_path(&self, path: &str) -> Option<HashMap<String, String>> {
+        let parts: Vec<&str> = split_path(path).collect();
+        let mut params = HashMap::new();
+
+        for (idx, segment) in self.segments.iter().enumerate() {
+            match segment {
+                // a trailing wildcard captures the rest of the path
+                Segment::Wildcard if idx == self.segments.len() - 1 => {
+                    let rest = parts.get(idx..).unwrap_or(&[]).join("/");
+                    params.insert(String::from("*"), decode_uri_component(&rest));
+                    return Some(params);
+                }
+                Segment::Wildcard => {
+                    parts.get(idx)?;
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), decode_uri_component(parts.get(idx)?));
+                }
+                Segment::Static(value) => {
+                    if parts.get(idx)? != value {
+                        return None;
+                    }
+                }
+            }
+        }
+        if parts.len() != self.segments.len() {
+            return None;
+        }
+        Some(params)
+    }
+
+    // lower sorts first, compared segment by segment: static < parameter < wildcard
+    fn specificity(&self) -> Vec<u8> {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Static(_) => 0,
+                Segment::Param(_) => 1,
+                Segment::Wildcard => 2,
+            })
+            .collect()
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Dispatches requests to the handler of the most specific matching route.
+///
+/// Route paths consist of static segments, named parameters (`/users/:id`) and
+/// wildcards (`*`). A wildcard in the last position matches the rest of the path
+/// and is captured as the `*` parameter, otherwise it matches any single segment.
+/// Static segments take priority over parameters and parameters over wildcards
+/// (`/users/me` wins over `/users/:id`), equally specific routes match in the order
+/// they were added.
+///
+/// A request for an existing path with a method no route accepts is answered with
+/// `405 METHOD_NOT_ALLOWED` and the `Allow` header, `OPTIONS` requests without a
+/// matching route are answered with the allowed methods.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Arc<dyn Handler>,
+    pub(crate) middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: vec![],
+            fallback: Arc::new(not_found),
+            middleware: vec![],
+        }
+    }
+
+    pub fn get<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(Some(Method::GET), path, handler)
+    }
+    pub fn head<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(Some(Method::HEAD), path, handler)
+    }
+    pub fn post<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(Some(Method::POST), path, handler)
+    }
+    pub fn put<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(Some(Method::PUT), path, handler)
+    }
+    pub fn patch<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(Some(Method::PATCH), path, handler)
+    }
+    pub fn delete<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(Some(Method::DELETE), path, handler)
+    }
+    pub fn options<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(Some(Method::OPTIONS), path, handler)
+    }
+    /// Registers a handler for all request methods.
+    pub fn all<F, Fut>(&mut self, path: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route(None, path, handler)
+    }
+
+    pub fn route(
+        &mut self,
+        method: Option<Method>,
+        path: &str,
+        handler: impl Handler,
+    ) -> &mut Router {
+        self.routes
+            .push(Route::new(method, path, Arc::new(handler)));
+        self
+    }
+
+    /// Sets the handler used when no route matches the request, by default a 404 page is sent.
+    pub fn fallback<F, Fut>(&mut self, handler: F) -> &mut Router
+    where
+        F: Fn(Request, Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.fallback = Arc::new(handler);
+        self
+    }
+
+    /// Adds a middleware to the chain, middleware runs in the order it was added before any route.
+    pub fn use_middleware<F, Fut>(&mut self, middleware: F) -> &mut Router
+    where
+        F: Fn(Request, Response, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Passes the request through the middleware chain and then to the matching route.
+    pub async fn dispatch(self: Arc<Self>, req: Request, res: Response) {
+        Next {
+            router: self,
+            index: 0,
+        }
+        .run(req, res)
+        .await
+    }
+
+    pub(crate) async fn run_routes(&self, mut req: Request, mut res: Response) {
+        // unknown methods never reach a handler, not even one registered for all methods
+        if req.method != Method::Unknown {
+            let matched = self
+                .routes
+                .iter()
+                .filter(|route| route.matches_method(&req.method))
+                .filter_map(|route| Some((route, route.matches_path(&req.path)?)))
+                .min_by_key(|(route, _)| route.specificity());
+            if let Some((route, params)) = matched {
+                req.params = params;
+                return route.handler.call(req, res).await;
+            }
+        }
+
+        // `OPTIONS *` asks about the server as a whole
+        let any_path = req.path == "*";
+        let has_route = any_path
+            || self
+                .routes
+                .iter()
+                .any(|route| route.matches_path(&req.path).is_some());
+        let is_options = req.method == Method::OPTIONS;
+        if !has_route && req.method != Method::Unknown {
+            return self.fallback.call(req, res).await;
+        }
+
+        let allowed = self.allowed_methods(&req.path, any_path || !has_route);
+        let allow = allowed
+            .iter()
+            .filter_map(|method| method.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        // method names are always valid header values
+        let _ = res.set_header("Allow", &allow);
+        let result = if is_options {
+            res.set_status(HTTPStatus::NoContent);
+            res.send(&[]).await
+        } else {
+            res.send_error(HTTPStatus::MethodNotAllowed).await
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to send the response: {}", e);
+        }
+    }
+
+    // methods which have a route for the path, OPTIONS is always answered automatically
+    fn allowed_methods(&self, path: &str, any_path: bool) -> Vec<Method> {
+        ALLOWED_METHODS
+            .iter()
+            .filter(|method| {
+                **method == Method::OPTIONS
+                    || self.routes.iter().any(|route| {
+                        route.matches_method(method)
+                            && (any_path || route.matches_path(path).is_some())
+                    })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+async fn not_found(_req: Request, mut res: Response) {
+    if let Err(e) = res.send_error(HTTPStatus::NotFound).await {
+        eprintln!("Failed to send the response: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::http::error_pages::ErrorPages;
+    use crate::core::shutdown::ShutdownHandle;
+    use crate::core::tcp::{ConnectionWriter, ResponseQueue, WriteHandle};
+    use crate::Error;
+    use async_trait::async_trait;
+    use std::sync::Mutex as StdMutex;
+    use tokio::sync::Mutex;
+
+    #[derive(Debug)]
+    struct Written(Arc<StdMutex<Vec<u8>>>);
+
+    #[async_trait]
+    impl ConnectionWriter for Written {
+        async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+        async fn shutdown(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    // routes the request and returns the raw response
+    async fn request(router: &Router, method: &str, path: &str) -> String {
+        let written = Arc::new(StdMutex::new(vec![]));
+        let write_handle: WriteHandle = Arc::new(Mutex::new(Box::new(Written(written.clone()))));
+        let head = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path);
+        let req = Request::new(head.into_bytes(), "127.0.0.1:8080".parse().unwrap()).unwrap();
+        let res = Response::__new(
+            Arc::new(Mutex::new(ResponseQueue::new(write_handle))),
+            0,
+            req.clone(),
+            false,
+            ShutdownHandle::new().signal().unwrap(),
+            Arc::new(ErrorPages::new()),
+        );
+        router.run_routes(req, res).await;
+        let response = written.lock().unwrap().clone();
+        String::from_utf8(response).unwrap()
+    }
+
+    // answers with the name of the route and the captured parameters
+    fn reply(name: &'static str) -> impl Fn(Request, Response) -> HandlerFuture + Send + Sync {
+        move |req: Request, mut res: Response| -> HandlerFuture {
+            Box::pin(async move {
+                let mut params: Vec<String> = req
+                    .params
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                params.sort();
+                res.set_status(HTTPStatus::Ok);
+                let body = format!("{} {}", name, params.join(" "));
+                res.send(body.trim_end().as_bytes()).await.unwrap();
+            })
+        }
+    }
+
+    fn body(response: &str) -> String {
+        let response = response.replace("\r\n", "\n");
+        response
+            .split_once("\n\n")
+            .map_or(String::new(), |(_, body)| body.to_owned())
+    }
+
+    #[tokio::test]
+    async fn captures_params_and_wildcards() {
+        let mut router = Router::new();
+        router
+            .get("/users/:id/posts/:post", reply("post"))
+            .get("/files/*", reply("files"))
+            .get("/a/*/c", reply("segment"));
+
+        let response = request(&router, "GET", "/users/42/posts/hello%20world").await;
+        assert_eq!(body(&response), "post id=42 post=hello world");
+
+        let response = request(&router, "GET", "/files/docs/readme.md").await;
+        assert_eq!(body(&response), "files *=docs/readme.md");
+
+        let response = request(&router, "GET", "/a/b/c").await;
+        assert_eq!(body(&response), "segment");
+    }
+
+    #[tokio::test]
+    async fn static_routes_take_priority() {
+        let mut router = Router::new();
+        router
+            .get("/users/*", reply("wildcard"))
+            .get("/users/:id", reply("param"))
+            .get("/users/me", reply("static"));
+
+        assert_eq!(body(&request(&router, "GET", "/users/me").await), "static");
+        assert_eq!(
+            body(&request(&router, "GET", "/users/7").await),
+            "param id=7"
+        );
+        assert_eq!(
+            body(&request(&router, "GET", "/users/7/friends").await),
+            "wildcard *=7/friends"
+        );
+    }
+
+    #[tokio::test]
+    async fn unmatched_paths_use_the_fallback() {
+        let mut router = Router::new();
+        router.get("/users/:id", reply("user"));
+
+        let response = request(&router, "GET", "/users").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        router.fallback(reply("fallback"));
+        let response = request(&router, "GET", "/missing").await;
+        assert_eq!(body(&response), "fallback");
+    }
+
+    #[tokio::test]
+    async fn get_routes_answer_head() {
+        let mut router = Router::new();
+        router.get("/", reply("index"));
+
+        let response = request(&router, "HEAD", "/").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Length: 5"));
+        assert_eq!(body(&response), "");
+    }
+
+    #[tokio::test]
+    async fn options_lists_allowed_methods() {
+        let mut router = Router::new();
+        router
+            .get("/users/:id", reply("get"))
+            .put("/users/:id", reply("put"))
+            .delete("/users/:id", reply("delete"));
+
+        let response = request(&router, "OPTIONS", "/users/1").await;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Allow: GET, HEAD, PUT, DELETE, OPTIONS"));
+
+        let response = request(&router, "OPTIONS", "*").await;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Allow: GET, HEAD, PUT, DELETE, OPTIONS"));
+    }
+
+    #[tokio::test]
+    async fn other_methods_are_not_allowed() {
+        let mut router = Router::new();
+        router
+            .get("/users/:id", reply("get"))
+            .patch("/users/:id", reply("patch"));
+
+        let response = request(&router, "POST", "/users/1").await;
+        assert!(response.starts_with("HTTP/1.1 405"));
+        assert!(response.contains("Allow: GET, HEAD, PATCH, OPTIONS"));
+
+        let response = request(&router, "BREW", "/users/1").await;
+        assert!(response.starts_with("HTTP/1.1 405"));
+    }
+
+    #[test]
+    fn parses_methods() {
+        let methods = [
+            ("PUT", Method::PUT),
+            ("PATCH", Method::PATCH),
+            ("DELETE", Method::DELETE),
+            ("OPTIONS", Method::OPTIONS),
+            ("CONNECT", Method::CONNECT),
+            ("TRACE", Method::TRACE),
+            ("BREW", Method::Unknown),
+        ];
+        for (name, method) in methods {
+            let head = format!("{} / HTTP/1.1\r\nHost: localhost\r\n\r\n", name);
+            let req = Request::new(head.into_bytes(), "127.0.0.1:8080".parse().unwrap()).unwrap();
+            assert_eq!(req.method, method);
+        }
+    }
+}
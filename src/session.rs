@@ -0,0 +1,536 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::core::http::{
+    cookies::{Cookie, CookieJar, SameSite},
+    protocol::{Request, Response},
+};
+use crate::error::SystemError;
+use crate::middleware::Next;
+use crate::router::HandlerFuture;
+use crate::Error;
+
+/// Values stored in a session, serialized as JSON values.
+pub type SessionData = HashMap<String, Value>;
+
+/// Storage backend for sessions.
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    /// Returns the data of the session, None if it doesn't exist or expired.
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, Error>;
+    /// Stores the data of the session, the session expires after `ttl` unless it is saved again.
+    async fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), Error>;
+    async fn destroy(&self, id: &str) -> Result<(), Error>;
+}
+
+#[derive(Debug)]
+struct SessionState {
+    id: String,
+    data: SessionData,
+    // the session wasn't loaded from the store
+    is_new: bool,
+    modified: bool,
+    destroyed: bool,
+    // the current state was written to the store
+    saved: bool,
+}
+
+impl SessionState {
+    fn changed(&mut self) {
+        self.modified = true;
+        self.saved = false;
+    }
+}
+
+/// The session of a request, see `Request::session`.
+///
+/// Clones share the same data. Changes have to be made before the response is sent,
+/// otherwise the session cookie of a new session isn't sent to the client.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<StdMutex<SessionState>>,
+}
+
+impl Session {
+    fn new(id: String, data: Option<SessionData>) -> Session {
+        Session {
+            state: Arc::new(StdMutex::new(SessionState {
+                id,
+                is_new: data.is_none(),
+                data: data.unwrap_or_default(),
+                modified: false,
+                destroyed: false,
+                saved: false,
+            })),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, SessionState> {
+        // the lock is never held across an await, a poisoned state is still consistent
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn id(&self) -> String {
+        self.state().id.clone()
+    }
+
+    /// Returns the value deserialized into `T`, None if it is missing or has a different type.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.state().data.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: &T) -> Result<(), Error> {
+        let value = serde_json::to_value(value).map_err(|e| {
+            Error::new(
+                "Failed to serialize the session value.",
+                SystemError::InvalidInput,
+            )
+            .with_source(e)
+        })?;
+        let mut state = self.state();
+        state.data.insert(key.to_owned(), value);
+        state.changed();
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.state();
+        if state.data.remove(key).is_some() {
+            state.changed();
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.state().data.contains_key(key)
+    }
+
+    /// Removes all values, the session id stays the same.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.changed();
+    }
+
+    /// Deletes the session from the store and the cookie from the client.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.destroyed = true;
+        state.saved = false;
+    }
+
+    /// Moves the data to a new session id (ex.: after logging in), the old session is deleted.
+    pub fn regenerate(&self) {
+        let mut state = self.state();
+        state.id = Uuid::new_v4().to_string();
+        state.is_new = true;
+        state.changed();
+    }
+
+    // new sessions are only stored once something was written to them
+    fn should_save(&self) -> bool {
+        let state = self.state();
+        !state.destroyed && (state.modified || !state.is_new)
+    }
+}
+
+/// Issues a session id cookie and loads the session of each request from a `SessionStore`.
+///
+/// Sessions are saved before the response head (with the cookie) is written, or after the handler
+/// finished if it changed the session later, and expire after `ttl` without a request.
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+}
+
+impl Sessions {
+    pub fn new<S: SessionStore>(store: S) -> Sessions {
+        Sessions {
+            store: Arc::new(store),
+            cookie_name: String::from("cree.sid"),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+        }
+    }
+
+    /// Name of the cookie holding the session id, `cree.sid` by default.
+    pub fn cookie_name(mut self, cookie_name: &str) -> Sessions {
+        self.cookie_name = cookie_name.to_owned();
+        self
+    }
+
+    /// Time after which an unused session expires, one day by default.
+    pub fn ttl(mut self, ttl: Duration) -> Sessions {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sends the session cookie only over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+        self
+    }
+
+    /// Creates a middleware which attaches the session to the request.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(Request, Response, Next) -> HandlerFuture + Send + Sync + 'static {
+        let sessions = Arc::new(self);
+        move |req, res, next| {
+            let sessions = sessions.clone();
+            Box::pin(async move { sessions.handle(req, res, next).await })
+        }
+    }
+
+    async fn handle(self: Arc<Self>, mut req: Request, mut res: Response, next: Next) {
+        let id = session_id(&req.cookies(), &self.cookie_name);
+        let data = match &id {
+            Some(id) => match self.store.load(id).await {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to load the session: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let original_id = data.as_ref().and(id);
+        let session = Session::new(
+            original_id
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            data,
+        );
+        req.set_session(session.clone());
+
+        // the cookie is refreshed with every response, so the expiry keeps moving
+        let hook_session = session.clone();
+        let sessions = self.clone();
+        res.on_send(move |res, body| {
+            let cookie = if hook_session.state().destroyed {
+                sessions.cookie("").map(Cookie::expire)
+            } else if hook_session.should_save() {
+                sessions.cookie(&hook_session.id())
+            } else {
+                return body;
+            };
+            if let Err(e) = cookie.and_then(|cookie| res.set_cookie(&cookie)) {
+                eprintln!("Failed to set the session cookie: {}", e);
+            }
+            body
+        });
+        // the client can send the next request as soon as it has the cookie
+        let hook_session = session.clone();
+        let sessions = self.clone();
+        let hook_id = original_id.clone();
+        res.before_send(move || {
+            Box::pin(async move { sessions.save(&hook_session, hook_id.as_deref()).await })
+        });
+
+        next.run(req, res).await;
+
+        // the response was never sent (ex.: an upgrade) or the session changed afterwards
+        if !session.state().saved {
+            self.save(&session, original_id.as_deref()).await;
+        }
+    }
+
+    async fn save(&self, session: &Session, original_id: Option<&str>) {
+        let (id, destroyed, data) = {
+            let mut state = session.state();
+            state.saved = true;
+            (state.id.clone(), state.destroyed, state.data.clone())
+        };
+        let result = if destroyed {
+            self.destroy(original_id).await
+        } else if session.should_save() {
+            match self.store.save(&id, &data, self.ttl).await {
+                // a regenerated session leaves the old id behind
+                Ok(()) if original_id != Some(id.as_str()) => self.destroy(original_id).await,
+                result => result,
+            }
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to save the session: {}", e);
+        }
+    }
+
+    async fn destroy(&self, id: Option<&str>) -> Result<(), Error> {
+        match id {
+            Some(id) => self.store.destroy(id).await,
+            None => Ok(()),
+        }
+    }
+
+    fn cookie(&self, id: &str) -> Result<Cookie, Error> {
+        Ok(Cookie::new(&self.cookie_name, id)?
+            .path("/")
+            .max_age(chrono::Duration::seconds(self.ttl.as_secs() as i64))
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax))
+    }
+}
+
+// ids which aren't a uuid are never passed to the store
+fn session_id(cookies: &CookieJar, cookie_name: &str) -> Option<String> {
+    cookies
+        .get(cookie_name)
+        .filter(|id| Uuid::parse_str(id).is_ok())
+        .map(|id| id.to_owned())
+}
+
+// expired sessions are removed at most this often
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps sessions in memory, they are lost when the server stops.
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+    last_purge: Mutex<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            last_purge: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, Error> {
+        let mut sessions = self.sessions.lock().await;
+        match sessions.get(id) {
+            Some((data, expires)) if *expires > Instant::now() => Ok(Some(data.clone())),
+            Some(_) => {
+                sessions.remove(id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), Error> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().await;
+        sessions.insert(id.to_owned(), (data.clone(), now + ttl));
+
+        let mut last_purge = self.last_purge.lock().await;
+        if now.duration_since(*last_purge) > PURGE_INTERVAL {
+            sessions.retain(|_, (_, expires)| *expires > now);
+            *last_purge = now;
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self, id: &str) -> Result<(), Error> {
+        self.sessions.lock().await.remove(id);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    // unix timestamp in seconds
+    expires: u64,
+    data: SessionData,
+}
+
+/// Keeps each session as a JSON file in a directory, sessions survive restarts.
+///
+/// Expired files are deleted when they are loaded.
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> FileStore {
+        FileStore {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf, Error> {
+        // the id becomes a file name, so only uuids are accepted
+        if Uuid::parse_str(id).is_err() || !id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+            return Err(Error::new(
+                "Invalid session id.",
+                SystemError::InvalidFileName,
+            ));
+        }
+        Ok(self.directory.join(format!("{}.json", id)))
+    }
+}
+
+#[async_trait]
+impl SessionStore for FileStore {
+    async fn load(&self, id: &str) -> Result<Option<SessionData>, Error> {
+        let path = self.path(id)?;
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(
+                    Error::new("Failed to read the session file.", SystemError::FileRead)
+                        .with_source(e),
+                )
+            }
+        };
+        let session: StoredSession = serde_json::from_slice(&content).map_err(|e| {
+            Error::new("Invalid session file.", SystemError::FileRead).with_source(e)
+        })?;
+        if session.expires <= unix_time() {
+            self.destroy(id).await?;
+            return Ok(None);
+        }
+        Ok(Some(session.data))
+    }
+
+    async fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), Error> {
+        let path = self.path(id)?;
+        let session = StoredSession {
+            expires: unix_time() + ttl.as_secs(),
+            data: data.clone(),
+        };
+        let content = serde_json::to_vec(&session).map_err(|e| {
+            Error::new(
+                "Failed to serialize the session.",
+                SystemError::InvalidInput,
+            )
+            .with_source(e)
+        })?;
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| {
+                Error::new(
+                    "Failed to create the session directory.",
+                    SystemError::FileWrite,
+                )
+                .with_source(e)
+            })?;
+        tokio::fs::write(&path, content).await.map_err(|e| {
+            Error::new("Failed to write the session file.", SystemError::FileWrite).with_source(e)
+        })
+    }
+
+    async fn destroy(&self, id: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.path(id)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::new(
+                "Failed to delete the session file.",
+                SystemError::FileWrite,
+            )
+            .with_source(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn data() -> SessionData {
+        let mut data = SessionData::new();
+        data.insert(String::from("visits"), Value::from(3));
+        data
+    }
+
+    fn temp_directory() -> PathBuf {
+        std::env::temp_dir().join(format!("cree-sessions-{}", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn memory_store_expires_sessions() {
+        let store = MemoryStore::new();
+        let id = Uuid::new_v4().to_string();
+        store
+            .save(&id, &data(), Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(store.load(&id).await.unwrap(), Some(data()));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(store.load(&id).await.unwrap(), None);
+        assert!(store.sessions.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn file_store_round_trip() {
+        let directory = temp_directory();
+        let store = FileStore::new(&directory);
+        let id = Uuid::new_v4().to_string();
+        store
+            .save(&id, &data(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(store.load(&id).await.unwrap(), Some(data()));
+
+        store.destroy(&id).await.unwrap();
+        assert_eq!(store.load(&id).await.unwrap(), None);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn file_store_deletes_expired_sessions() {
+        let directory = temp_directory();
+        let store = FileStore::new(&directory);
+        let id = Uuid::new_v4().to_string();
+        store.save(&id, &data(), Duration::ZERO).await.unwrap();
+        assert!(store.path(&id).unwrap().exists());
+
+        assert_eq!(store.load(&id).await.unwrap(), None);
+        assert!(!store.path(&id).unwrap().exists());
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_session_ids() {
+        let cookies = CookieJar::parse("cree.sid=../../etc/passwd; other=1");
+        assert_eq!(session_id(&cookies, "cree.sid"), None);
+        let id = Uuid::new_v4().to_string();
+        let cookies = CookieJar::parse(&format!("cree.sid={}", id));
+        assert_eq!(session_id(&cookies, "cree.sid"), Some(id));
+
+        let store = FileStore::new(temp_directory());
+        for id in ["", "../session", "abc", "0123456789abcdef-"] {
+            let result = store.load(id).await.map_err(|e| e.kind());
+            assert_eq!(
+                result,
+                Err(ErrorKind::System(SystemError::InvalidFileName)),
+                "{:?}",
+                id
+            );
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    io::SeekFrom,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use chrono::{DateTime, Utc};
+use tokio::{fs::File, io::AsyncSeekExt};
+
+use crate::core::http::{
+    codes::HTTPStatus,
+    decode_uri_component, format_http_date,
+    mime::get_mime_type,
+    protocol::{Method, Request, Response},
+    Range,
+};
+use crate::error::SystemError;
+use crate::middleware::Next;
+use crate::router::HandlerFuture;
+use crate::{CreeOptions, Error, M_BYTE};
+
+/// Serves files from a root directory.
+///
+/// Supports `Range` requests (one range, answered with `206 PARTIAL_CONTENT` or
+/// `416 RANGE_NOT_SATISFIABLE`), conditional requests using `If-None-Match` and
+/// `If-Modified-Since` (answered with `304 NOT_MODIFIED`) and directory index files.
+/// Files are sent with a `Content-Length` header and are not read for HEAD requests.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+    chunk_size: usize,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index_files: vec![String::from("index.html")],
+            chunk_size: M_BYTE,
+        }
+    }
+
+    /// Uses the `root_directory` and `pc_chunk_size` fields of the config, None if no root directory is set.
+    pub fn from_options(options: &CreeOptions) -> Option<StaticFiles> {
+        let mut static_files = StaticFiles::new(options.root_directory.clone()?);
+        if let Some(chunk_size) = options.pc_chunk_size {
+            static_files.chunk_size = chunk_size;
+        }
+        Some(static_files)
+    }
+
+    /// Files tried in order when a directory is requested.
+    pub fn index_files(mut self, index_files: &[&str]) -> StaticFiles {
+        self.index_files = index_files.iter().map(|&file| file.to_owned()).collect();
+        self
+    }
+
+    /// Number of bytes sent for a range without an end (ex.: `bytes=1024-`).
+    pub fn chunk_size(mut self, chunk_size: usize) -> StaticFiles {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Creates a middleware which serves the requested file, or passes the request on if there is none.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(Request, Response, Next) -> HandlerFuture + Send + Sync + 'static {
+        let static_files = Arc::new(self);
+        move |req, mut res, next| {
+            let static_files = static_files.clone();
+            Box::pin(async move {
+                match static_files.serve(&req, &mut res).await {
+                    Ok(true) => {}
+                    Ok(false) => next.run(req, res).await,
+                    Err(e) => eprintln!("Failed to serve a static file: {}", e),
+                }
+            })
+        }
+    }
+
+    /// Sends the requested file, returns false if the request doesn't target an existing file.
+    pub async fn serve(&self, req: &Request, res: &mut Response) -> Result<bool, Error> {
+        if req.method != Method::GET && req.method != Method::HEAD {
+            return Ok(false);
+        }
+        let path = match self.resolve(&req.path).await {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let mut file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(_) => return Ok(false),
+        };
+        let metadata = file.metadata().await.map_err(|e| {
+            Error::new("Failed to read the file metadata.", SystemError::FileRead).with_source(e)
+        })?;
+        let length = metadata.len() as usize;
+
+        // validators used for caching
+        let modified: Option<DateTime<Utc>> = metadata.modified().ok().map(DateTime::from);
+        let etag = format!(
+            "W/\"{:x}-{:x}\"",
+            length,
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_secs())
+        );
+
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        res.set_header("Content-Type", &get_mime_type(&extension.to_lowercase()))?;
+        res.set_header("Accept-Ranges", "bytes")?;
+        res.set_header("ETag", &etag)?;
+        if let Some(modified) = modified {
+            res.set_header("Last-Modified", &format_http_date(&modified))?;
+        }
+
+        if is_not_modified(req, &etag, modified) {
+            res.set_status(HTTPStatus::NotModified);
+            res.remove_header("Content-Type");
+            res.send(&[]).await?;
+            return Ok(true);
+        }
+
+        // a Range header is ignored if If-Range doesn't match the current version of the file
+        let range = match (req.headers.get("range"), req.headers.get("if-range")) {
+            (Some(range), None) => Some(range),
+            (Some(range), Some(if_range)) if if_range_matches(if_range, modified) => Some(range),
+            _ => None,
+        };
+        // invalid or multiple ranges are ignored and the whole file is sent
+        let range = range.and_then(Range::parse);
+
+        let (from, to) = match range {
+            Some(range) => match self.byte_range(&range, length) {
+                Some(byte_range) => byte_range,
+                None => {
+                    res.set_status(HTTPStatus::RangeNotSatisfiable);
+                    res.set_header("Content-Range", &format!("bytes */{}", length))?;
+                    res.send(&[]).await?;
+                    return Ok(true);
+                }
+            },
+            None => {
+                res.set_status(HTTPStatus::Ok);
+                res.send_reader_sized(file, length).await?;
+                return Ok(true);
+            }
+        };
+
+        res.set_status(HTTPStatus::PartialContent);
+        res.set_header(
+            "Content-Range",
+            &format!("bytes {}-{}/{}", from, to, length),
+        )?;
+        if req.method != Method::HEAD {
+            file.seek(SeekFrom::Start(from as u64)).await.map_err(|e| {
+                Error::new("Failed to read the file.", SystemError::FileRead).with_source(e)
+            })?;
+        }
+        res.send_reader_sized(file, to - from + 1).await?;
+        Ok(true)
+    }
+
+    // converts the requested range to inclusive byte positions, None if it can't be satisfied
+    fn byte_range(&self, range: &Range, length: usize) -> Option<(usize, usize)> {
+        if length == 0 {
+            return None;
+        }
+        let (from, to) = match (range.from, range.to) {
+            // the last n bytes
+            (None, Some(suffix)) => (length - suffix.min(length), length - 1),
+            // one chunk starting at from
+            (Some(from), None) => (from, from.saturating_add(self.chunk_size.saturating_sub(1))),
+            (Some(from), Some(to)) => (from, to),
+            (None, None) => return None,
+        };
+        if from >= length || (range.from.is_none() && range.to == Some(0)) {
+            return None;
+        }
+        Some((from, to.min(length - 1)))
+    }
+
+    // maps the request path to a file inside the root directory
+    async fn resolve(&self, request_path: &str) -> Option<PathBuf> {
+        let request_path = decode_uri_component(request_path);
+        if request_path.contains('\0') {
+            return None;
+        }
+
+        let mut path = self.root.clone();
+        for component in Path::new(&request_path).components() {
+            match component {
+                Component::Normal(segment) => path.push(segment),
+                Component::RootDir | Component::CurDir => {}
+                // never leave the root directory
+                Component::ParentDir | Component::Prefix(_) => return None,
+            }
+        }
+
+        // symbolic links could still point outside of the root directory
+        let root = tokio::fs::canonicalize(&self.root).await.ok()?;
+        let mut path = tokio::fs::canonicalize(&path).await.ok()?;
+        if !path.starts_with(&root) {
+            return None;
+        }
+
+        if tokio::fs::metadata(&path).await.ok()?.is_dir() {
+            let mut index_file = None;
+            for file in &self.index_files {
+                let candidate = path.join(file);
+                if tokio::fs::metadata(&candidate)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_file())
+                {
+                    index_file = Some(candidate);
+                    break;
+                }
+            }
+            path = index_file?;
+        }
+        Some(path)
+    }
+}
+
+// the ETag is weak, which If-Range must not use (RFC 9110 13.1.5), so only a Last-Modified date
+// matches, and only if it is strong: at least one second older than the response
+fn if_range_matches(if_range: &str, modified: Option<DateTime<Utc>>) -> bool {
+    let modified = match modified {
+        Some(modified) if Utc::now().timestamp() - modified.timestamp() >= 1 => modified,
+        _ => return false,
+    };
+    match DateTime::parse_from_rfc2822(if_range.trim()) {
+        Ok(date) => date.timestamp() == modified.timestamp(),
+        Err(_) => false,
+    }
+}
+
+fn is_not_modified(req: &Request, etag: &str, modified: Option<DateTime<Utc>>) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since
+    if let Some(if_none_match) = req.headers.get("if-none-match") {
+        let etag = etag.trim_start_matches("W/");
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    if let (Some(if_modified_since), Some(modified)) =
+        (req.headers.get("if-modified-since"), modified)
+    {
+        if let Ok(since) = DateTime::parse_from_rfc2822(if_modified_since.trim()) {
+            return modified.timestamp() <= since.timestamp();
+        }
+    }
+    false
+}
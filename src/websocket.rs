@@ -0,0 +1,785 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::core::http::{
+    codes::HTTPStatus,
+    protocol::{Method, Request, Response},
+};
+use crate::core::tcp::{ConnectionReader, RawReader, WriteHandle};
+use crate::error::WebSocketError;
+use crate::Error;
+
+// appended to the Sec-WebSocket-Key before hashing (RFC 6455 section 1.3)
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const BUFFER_SIZE: usize = 8192;
+/// Messages above this size are split into multiple frames.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+/// Default limit of a received message, larger messages close the connection.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+// frame opcodes
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Status codes sent in close frames.
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    pub const INVALID_PAYLOAD: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Pings are answered automatically.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The client started or answered the closing handshake, no more messages follow.
+    Close(Option<CloseFrame>),
+}
+
+/// Whether the request is a WebSocket handshake.
+pub fn is_websocket_request(req: &Request) -> bool {
+    req.is_upgrade()
+        && req
+            .headers
+            .get("upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+/// Computes the `Sec-WebSocket-Accept` value for the `Sec-WebSocket-Key` of a handshake.
+pub fn accept_key(key: &str) -> String {
+    let hash = Sha1::digest(format!("{}{}", key.trim(), WEBSOCKET_GUID).as_bytes());
+    base64::encode(hash)
+}
+
+/// A WebSocket connection established with `WebSocket::upgrade`.
+pub struct WebSocket {
+    receiver: WebSocketReceiver,
+    sender: WebSocketSender,
+}
+
+impl WebSocket {
+    /// Completes the handshake of a WebSocket request and takes over its connection.
+    ///
+    /// Headers set on the response (ex.: `Sec-WebSocket-Protocol`) are sent with the handshake.
+    /// An invalid handshake is answered with `400 Bad Request` (or `426 Upgrade Required`
+    /// for unsupported versions) and an error is returned.
+    pub async fn upgrade(req: &Request, mut res: Response) -> Result<WebSocket, Error> {
+        let key = match validate_handshake(req) {
+            Ok(key) => key,
+            Err((status, message)) => {
+                if let HTTPStatus::UpgradeRequired = status {
+                    res.set_header("Sec-WebSocket-Version", "13")?;
+                }
+                res.set_status(status);
+                res.set_header("Content-Type", "text/plain")?;
+                let _ = res.send(message.as_bytes()).await;
+                return Err(Error::new(message, WebSocketError::InvalidHandshake));
+            }
+        };
+        let upgrade = req.take_upgrade().await.ok_or(Error::new(
+            "The connection was already upgraded.",
+            WebSocketError::InvalidHandshake,
+        ))?;
+
+        res.set_header("Upgrade", "websocket")?;
+        res.set_header("Connection", "Upgrade")?;
+        res.set_header("Sec-WebSocket-Accept", &accept_key(&key))?;
+        let write_handle = res.switch_protocols().await.map_err(|e| {
+            Error::new(
+                "Failed to switch protocols.",
+                WebSocketError::InvalidHandshake,
+            )
+            .with_source(e)
+        })?;
+        let RawReader { reader, buffered } = upgrade.accept().await?;
+
+        let sender = WebSocketSender {
+            write_handle,
+            close_sent: Arc::new(AtomicBool::new(false)),
+        };
+        Ok(WebSocket {
+            receiver: WebSocketReceiver {
+                reader,
+                buffer: buffered,
+                sender: sender.clone(),
+                fragments: None,
+                max_message_size: MAX_MESSAGE_SIZE,
+                closed: false,
+            },
+            sender,
+        })
+    }
+
+    /// Limit of a received message, a larger message closes the connection with code 1009.
+    pub fn max_message_size(mut self, max_message_size: usize) -> WebSocket {
+        self.receiver.max_message_size = max_message_size;
+        self
+    }
+
+    /// Returns the next message, None once the connection is closed.
+    pub async fn receive(&mut self) -> Result<Option<Message>, Error> {
+        self.receiver.receive().await
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), Error> {
+        self.sender.send(message).await
+    }
+    pub async fn send_text(&mut self, text: &str) -> Result<(), Error> {
+        self.sender.send_text(text).await
+    }
+    pub async fn send_binary(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.sender.send_binary(data).await
+    }
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        self.sender.close(code, reason).await
+    }
+
+    /// Splits the connection, so messages can be sent while another task waits for incoming ones.
+    pub fn split(self) -> (WebSocketReceiver, WebSocketSender) {
+        (self.receiver, self.sender)
+    }
+}
+
+fn validate_handshake(req: &Request) -> Result<String, (HTTPStatus, &'static str)> {
+    if !is_websocket_request(req) {
+        return Err((HTTPStatus::BadRequest, "Not a WebSocket handshake."));
+    }
+    if req.method != Method::GET {
+        return Err((HTTPStatus::BadRequest, "WebSocket handshakes use GET."));
+    }
+    if req.headers.get("sec-websocket-version").map(str::trim) != Some("13") {
+        return Err((
+            HTTPStatus::UpgradeRequired,
+            "Unsupported WebSocket version.",
+        ));
+    }
+    let key = req.headers.get("sec-websocket-key").unwrap_or("").trim();
+    // the key is a base64 encoded 16 byte nonce
+    if base64::decode(key).map_or(true, |nonce| nonce.len() != 16) {
+        return Err((HTTPStatus::BadRequest, "Invalid Sec-WebSocket-Key header."));
+    }
+    Ok(key.to_owned())
+}
+
+/// Sends messages over a WebSocket, clones send over the same connection.
+#[derive(Clone)]
+pub struct WebSocketSender {
+    write_handle: WriteHandle,
+    close_sent: Arc<AtomicBool>,
+}
+
+impl WebSocketSender {
+    pub async fn send(&self, message: Message) -> Result<(), Error> {
+        match message {
+            Message::Text(text) => self.send_text(&text).await,
+            Message::Binary(data) => self.send_binary(&data).await,
+            Message::Ping(data) => self.send_control(PING, &data).await,
+            Message::Pong(data) => self.send_control(PONG, &data).await,
+            Message::Close(Some(CloseFrame { code, reason })) => self.close(code, &reason).await,
+            Message::Close(None) => self.send_close(&[]).await,
+        }
+    }
+
+    pub async fn send_text(&self, text: &str) -> Result<(), Error> {
+        self.send_data(TEXT, text.as_bytes()).await
+    }
+
+    pub async fn send_binary(&self, data: &[u8]) -> Result<(), Error> {
+        self.send_data(BINARY, data).await
+    }
+
+    pub async fn ping(&self, data: &[u8]) -> Result<(), Error> {
+        self.send_control(PING, data).await
+    }
+
+    /// Starts the closing handshake, the client answers with a close message.
+    /// Fails for codes which must not be sent (ex.: `1005`, `1006` and `1015`).
+    pub async fn close(&self, code: u16, reason: &str) -> Result<(), Error> {
+        if !is_valid_close_code(code) {
+            return Err(Error::new(
+                "Invalid close code.",
+                WebSocketError::InvalidFrame,
+            ));
+        }
+        let payload = [&code.to_be_bytes()[..], reason.as_bytes()].concat();
+        self.send_close(&payload).await
+    }
+
+    // data messages are fragmented, all fragments are written under one lock so they aren't interleaved
+    async fn send_data(&self, opcode: u8, data: &[u8]) -> Result<(), Error> {
+        if self.close_sent.load(Ordering::SeqCst) {
+            return Err(Error::new(
+                "The WebSocket is closing.",
+                WebSocketError::Closing,
+            ));
+        }
+        let mut frames = vec![];
+        let mut fragments = data.chunks(MAX_FRAME_SIZE).peekable();
+        let mut frame_opcode = opcode;
+        if fragments.peek().is_none() {
+            frames.extend(encode_frame(true, opcode, &[]));
+        }
+        while let Some(fragment) = fragments.next() {
+            let fin = fragments.peek().is_none();
+            frames.extend(encode_frame(fin, frame_opcode, fragment));
+            frame_opcode = CONTINUATION;
+        }
+        self.write_handle.lock().await.write(&frames).await
+    }
+
+    async fn send_control(&self, opcode: u8, data: &[u8]) -> Result<(), Error> {
+        // control frames can't be fragmented
+        if data.len() > 125 {
+            return Err(Error::new(
+                "Control frame payload is too large.",
+                WebSocketError::InvalidFrame,
+            ));
+        }
+        if self.close_sent.load(Ordering::SeqCst) {
+            return Err(Error::new(
+                "The WebSocket is closing.",
+                WebSocketError::Closing,
+            ));
+        }
+        self.write_handle
+            .lock()
+            .await
+            .write(&encode_frame(true, opcode, data))
+            .await
+    }
+
+    async fn send_close(&self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > 125 {
+            return Err(Error::new(
+                "Close reason is too long.",
+                WebSocketError::InvalidFrame,
+            ));
+        }
+        // only one close frame is ever sent
+        if self.close_sent.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.write_handle
+            .lock()
+            .await
+            .write(&encode_frame(true, CLOSE, payload))
+            .await
+    }
+
+    async fn shutdown(&self) -> Result<(), Error> {
+        self.write_handle.lock().await.shutdown().await
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Receives messages from a WebSocket, answers pings and the closing handshake.
+pub struct WebSocketReceiver {
+    reader: Box<dyn ConnectionReader>,
+    buffer: Vec<u8>,
+    sender: WebSocketSender,
+    // opcode and data of a fragmented message
+    fragments: Option<(u8, Vec<u8>)>,
+    max_message_size: usize,
+    closed: bool,
+}
+
+impl WebSocketReceiver {
+    /// Returns the next message, None once the connection is closed.
+    pub async fn receive(&mut self) -> Result<Option<Message>, Error> {
+        while !self.closed {
+            let frame = match self.read_frame().await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    self.closed = true;
+                    return Ok(None);
+                }
+                Err((code, e)) => {
+                    self.fail(code).await;
+                    return Err(e);
+                }
+            };
+            match self.handle_frame(frame).await {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) => {}
+                Err((code, e)) => {
+                    self.fail(code).await;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    async fn handle_frame(&mut self, frame: Frame) -> Result<Option<Message>, (u16, Error)> {
+        match frame.opcode {
+            PING => {
+                // the closing handshake may already have started, the pong is then skipped
+                let _ = self.sender.send_control(PONG, &frame.payload).await;
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            PONG => Ok(Some(Message::Pong(frame.payload))),
+            CLOSE => {
+                let close_frame = parse_close_payload(&frame.payload)?;
+                // echo the code to complete the closing handshake, then close the TCP connection
+                let payload = frame.payload.get(..2).unwrap_or(&[]).to_vec();
+                let _ = self.sender.send_close(&payload).await;
+                let _ = self.sender.shutdown().await;
+                self.closed = true;
+                Ok(Some(Message::Close(close_frame)))
+            }
+            TEXT | BINARY => {
+                if self.fragments.is_some() {
+                    return Err(protocol_error("Expected a continuation frame."));
+                }
+                if frame.fin {
+                    return to_message(frame.opcode, frame.payload).map(Some);
+                }
+                self.fragments = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            CONTINUATION => {
+                let (opcode, mut data) = self
+                    .fragments
+                    .take()
+                    .ok_or_else(|| protocol_error("Unexpected continuation frame."))?;
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err(too_big());
+                }
+                data.extend(frame.payload);
+                if frame.fin {
+                    return to_message(opcode, data).map(Some);
+                }
+                self.fragments = Some((opcode, data));
+                Ok(None)
+            }
+            _ => Err(protocol_error("Unknown opcode.")),
+        }
+    }
+
+    // reads the next frame, None if the connection was closed
+    async fn read_frame(&mut self) -> Result<Option<Frame>, (u16, Error)> {
+        loop {
+            if let Some((frame, length)) = decode_frame(&self.buffer, self.max_message_size)? {
+                self.buffer.drain(..length);
+                return Ok(Some(frame));
+            }
+            let mut buffer = [0u8; BUFFER_SIZE];
+            match self.reader.read(&mut buffer).await {
+                Ok(0) | Err(_) => return Ok(None),
+                Ok(length) => self.buffer.extend_from_slice(&buffer[..length]),
+            }
+        }
+    }
+
+    // closes the connection after a protocol violation
+    async fn fail(&mut self, code: u16) {
+        let _ = self.sender.send_close(&code.to_be_bytes()).await;
+        let _ = self.sender.shutdown().await;
+        self.closed = true;
+    }
+}
+
+// parses a frame from the start of the buffer, returns the frame and its length
+fn decode_frame(buffer: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>, (u16, Error)> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buffer[0] & 0x80 != 0;
+    let rsv = buffer[0] & 0x70;
+    let opcode = buffer[0] & 0x0F;
+    let masked = buffer[1] & 0x80 != 0;
+
+    // no extensions are negotiated, so the reserved bits have to be 0
+    if rsv != 0 {
+        return Err(protocol_error("Reserved bits are set."));
+    }
+    // all frames from the client have to be masked
+    if !masked {
+        return Err(protocol_error("Frames from the client have to be masked."));
+    }
+    let is_control = opcode & 0x8 != 0;
+    if is_control && !fin {
+        return Err(protocol_error("Control frames can't be fragmented."));
+    }
+
+    let (length, mut offset) = match buffer[1] & 0x7F {
+        126 => match buffer.get(2..4) {
+            Some(length) => (u16::from_be_bytes([length[0], length[1]]) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match buffer.get(2..10) {
+            Some(length) => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(length);
+                (u64::from_be_bytes(bytes), 10)
+            }
+            None => return Ok(None),
+        },
+        length => (length as u64, 2),
+    };
+    if is_control && length > 125 {
+        return Err(protocol_error("Control frame payload is too large."));
+    }
+    if length > max_size as u64 {
+        return Err(too_big());
+    }
+    let length = length as usize;
+
+    let mask = match buffer.get(offset..offset + 4) {
+        Some(mask) => [mask[0], mask[1], mask[2], mask[3]],
+        None => return Ok(None),
+    };
+    offset += 4;
+    let payload = match buffer.get(offset..offset + length) {
+        Some(payload) => payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4])
+            .collect(),
+        None => return Ok(None),
+    };
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        offset + length,
+    )))
+}
+
+// server frames are never masked
+fn encode_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![if fin { 0x80 } else { 0x00 } | opcode];
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend((length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend((length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn to_message(opcode: u8, data: Vec<u8>) -> Result<Message, (u16, Error)> {
+    if opcode == BINARY {
+        return Ok(Message::Binary(data));
+    }
+    String::from_utf8(data).map(Message::Text).or(Err((
+        close_code::INVALID_PAYLOAD,
+        Error::new(
+            "Text message is not valid UTF-8.",
+            WebSocketError::InvalidFrame,
+        ),
+    )))
+}
+
+fn parse_close_payload(payload: &[u8]) -> Result<Option<CloseFrame>, (u16, Error)> {
+    match payload.len() {
+        0 => Ok(None),
+        1 => Err(protocol_error("Invalid close frame.")),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            if !is_valid_close_code(code) {
+                return Err(protocol_error("Invalid close code."));
+            }
+            let reason = String::from_utf8(payload[2..].to_vec()).or(Err((
+                close_code::INVALID_PAYLOAD,
+                Error::new(
+                    "Close reason is not valid UTF-8.",
+                    WebSocketError::InvalidFrame,
+                ),
+            )))?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+// codes a peer may send (RFC 6455 7.4), 1005, 1006 and 1015 must never be sent in a close frame
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+fn protocol_error(message: &str) -> (u16, Error) {
+    (
+        close_code::PROTOCOL_ERROR,
+        Error::new(message, WebSocketError::InvalidFrame),
+    )
+}
+
+fn too_big() -> (u16, Error) {
+    (
+        close_code::MESSAGE_TOO_BIG,
+        Error::new("Message is too large.", WebSocketError::MessageTooLarge),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tcp::ConnectionWriter;
+    use async_trait::async_trait;
+    use std::sync::Mutex as StdMutex;
+    use tokio::sync::Mutex;
+
+    // a client frame with the given mask
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = encode_frame(fin, opcode, payload);
+        let header_length = frame.len() - payload.len();
+        frame[1] |= 0x80;
+        let masked = payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4]);
+        frame.truncate(header_length);
+        frame.extend(mask);
+        frame.extend(masked);
+        frame
+    }
+
+    fn error_code(result: Result<Option<(Frame, usize)>, (u16, Error)>) -> Option<u16> {
+        result.err().map(|(code, _)| code)
+    }
+
+    struct EmptyReader;
+
+    #[async_trait]
+    impl ConnectionReader for EmptyReader {
+        async fn read(&mut self, _buffer: &mut [u8]) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Written(Arc<StdMutex<Vec<u8>>>);
+
+    #[async_trait]
+    impl ConnectionWriter for Written {
+        async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+        async fn shutdown(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    // a receiver which reads the frames, returns it with everything the server writes
+    fn test_receiver(frames: &[Vec<u8>]) -> (WebSocketReceiver, Arc<StdMutex<Vec<u8>>>) {
+        let written = Arc::new(StdMutex::new(vec![]));
+        let write_handle: WriteHandle = Arc::new(Mutex::new(Box::new(Written(written.clone()))));
+        let sender = WebSocketSender {
+            write_handle,
+            close_sent: Arc::new(AtomicBool::new(false)),
+        };
+        let receiver = WebSocketReceiver {
+            reader: Box::new(EmptyReader),
+            buffer: frames.concat(),
+            sender,
+            fragments: None,
+            max_message_size: MAX_MESSAGE_SIZE,
+            closed: false,
+        };
+        (receiver, written)
+    }
+
+    #[test]
+    fn accept_key_of_rfc_sample() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn decode_masked_frame() {
+        let frame = client_frame(true, TEXT, b"Hello");
+        // incomplete frames wait for more data
+        for length in 0..frame.len() {
+            assert!(decode_frame(&frame[..length], 1024).unwrap().is_none());
+        }
+        let (decoded, length) = decode_frame(&frame, 1024).unwrap().unwrap();
+        assert!(decoded.fin);
+        assert_eq!(decoded.opcode, TEXT);
+        assert_eq!(decoded.payload, b"Hello");
+        assert_eq!(length, frame.len());
+    }
+
+    #[test]
+    fn reject_invalid_frames() {
+        // unmasked client frame
+        let frame = encode_frame(true, TEXT, b"Hello");
+        assert_eq!(error_code(decode_frame(&frame, 1024)), Some(1002));
+
+        // reserved bits
+        let mut frame = client_frame(true, TEXT, b"Hello");
+        frame[0] |= 0x40;
+        assert_eq!(error_code(decode_frame(&frame, 1024)), Some(1002));
+
+        // control frames can't be longer than 125 bytes or fragmented
+        let frame = client_frame(true, PING, &[0; 126]);
+        assert_eq!(error_code(decode_frame(&frame, 1024)), Some(1002));
+        let frame = client_frame(false, PING, b"ping");
+        assert_eq!(error_code(decode_frame(&frame, 1024)), Some(1002));
+
+        // larger than the message limit
+        let frame = client_frame(true, BINARY, &[0; 200]);
+        assert_eq!(error_code(decode_frame(&frame, 100)), Some(1009));
+    }
+
+    #[test]
+    fn length_encodings() {
+        let frame = encode_frame(true, BINARY, &[1; 125]);
+        assert_eq!(frame[..2], [0x82, 125]);
+        assert_eq!(frame.len(), 2 + 125);
+
+        // 16-bit length
+        let frame = encode_frame(true, BINARY, &[1; 126]);
+        assert_eq!(frame[..4], [0x82, 126, 0, 126]);
+        let frame = encode_frame(false, TEXT, &[1; 65535]);
+        assert_eq!(frame[..4], [0x01, 126, 0xFF, 0xFF]);
+
+        // 64-bit length
+        let frame = encode_frame(true, BINARY, &[1; 65536]);
+        assert_eq!(frame[..10], [0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(frame.len(), 10 + 65536);
+
+        // client frames with both encodings decode to the same payload
+        for length in [126, 65536] {
+            let frame = client_frame(true, BINARY, &vec![7; length]);
+            let (decoded, _) = decode_frame(&frame, MAX_MESSAGE_SIZE).unwrap().unwrap();
+            assert_eq!(decoded.payload, vec![7; length]);
+        }
+    }
+
+    #[test]
+    fn close_codes() {
+        for code in [0u16, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000] {
+            let payload = code.to_be_bytes();
+            assert_eq!(
+                parse_close_payload(&payload).err().map(|(code, _)| code),
+                Some(1002),
+                "{}",
+                code
+            );
+        }
+        for code in [1000u16, 1001, 1003, 1007, 1011, 3000, 4999] {
+            let payload = [&code.to_be_bytes()[..], b"bye"].concat();
+            assert_eq!(
+                parse_close_payload(&payload).unwrap(),
+                Some(CloseFrame {
+                    code,
+                    reason: String::from("bye")
+                })
+            );
+        }
+        assert!(parse_close_payload(&[0x03]).is_err());
+        assert_eq!(parse_close_payload(&[]).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn fragmented_messages() {
+        // a ping can arrive between the fragments
+        let (mut receiver, written) = test_receiver(&[
+            client_frame(false, TEXT, b"Hel"),
+            client_frame(true, PING, b"ping"),
+            client_frame(false, CONTINUATION, b"lo "),
+            client_frame(true, CONTINUATION, b"world"),
+        ]);
+        let message = receiver.receive().await.unwrap();
+        assert_eq!(message, Some(Message::Ping(b"ping".to_vec())));
+        let message = receiver.receive().await.unwrap();
+        assert_eq!(message, Some(Message::Text(String::from("Hello world"))));
+        assert_eq!(*written.lock().unwrap(), encode_frame(true, PONG, b"ping"));
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_fragmentation() {
+        let (mut receiver, written) = test_receiver(&[client_frame(true, CONTINUATION, b"data")]);
+        assert!(receiver.receive().await.is_err());
+        assert_eq!(
+            *written.lock().unwrap(),
+            encode_frame(true, CLOSE, &[0x03, 0xEA])
+        );
+
+        let (mut receiver, written) = test_receiver(&[
+            client_frame(false, TEXT, b"Hel"),
+            client_frame(true, TEXT, b"lo"),
+        ]);
+        assert!(receiver.receive().await.is_err());
+        assert_eq!(
+            *written.lock().unwrap(),
+            encode_frame(true, CLOSE, &[0x03, 0xEA])
+        );
+        assert_eq!(receiver.receive().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn closing_handshake() {
+        let (mut receiver, written) = test_receiver(&[client_frame(true, CLOSE, &[0x03, 0xE8])]);
+        let message = receiver.receive().await.unwrap();
+        assert_eq!(
+            message,
+            Some(Message::Close(Some(CloseFrame {
+                code: 1000,
+                reason: String::new()
+            })))
+        );
+        assert_eq!(
+            *written.lock().unwrap(),
+            encode_frame(true, CLOSE, &[0x03, 0xE8])
+        );
+
+        // a code which must not be sent is answered with 1002
+        let (mut receiver, written) = test_receiver(&[client_frame(true, CLOSE, &[0x03, 0xED])]);
+        assert!(receiver.receive().await.is_err());
+        assert_eq!(
+            *written.lock().unwrap(),
+            encode_frame(true, CLOSE, &[0x03, 0xEA])
+        );
+
+        // the server can't send them either
+        let (receiver, written) = test_receiver(&[]);
+        assert!(receiver.sender.close(1005, "").await.is_err());
+        assert!(receiver.sender.close(999, "").await.is_err());
+        assert!(written.lock().unwrap().is_empty());
+        receiver.sender.close(1001, "bye").await.unwrap();
+        assert_eq!(
+            *written.lock().unwrap(),
+            encode_frame(true, CLOSE, &[&[0x03, 0xE9][..], b"bye"].concat())
+        );
+    }
+}